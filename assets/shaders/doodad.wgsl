@@ -0,0 +1,91 @@
+#import bevy_pbr::mesh_view_bindings
+#import bevy_pbr::mesh_bindings
+
+#import bevy_pbr::pbr_types
+#import bevy_pbr::utils
+#import bevy_pbr::clustered_forward
+#import bevy_pbr::lighting
+#import bevy_pbr::shadows
+#import bevy_pbr::pbr_functions
+
+@group(2) @binding(0)
+var base_texture: texture_2d<f32>;
+@group(2) @binding(1)
+var base_texture_sampler: sampler;
+
+struct Vertex {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+
+    // The placement's transform, one column per location from the instance buffer.
+    @location(3) transform_0: vec4<f32>,
+    @location(4) transform_1: vec4<f32>,
+    @location(5) transform_2: vec4<f32>,
+    @location(6) transform_3: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    #import bevy_pbr::mesh_vertex_output
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    let transform = mat4x4<f32>(vertex.transform_0, vertex.transform_1, vertex.transform_2, vertex.transform_3);
+
+    var out: VertexOutput;
+    out.world_position = transform * vec4<f32>(vertex.position, 1.0);
+    // Placements are only ever uniformly scaled, so the transform itself is fine for normals.
+    out.world_normal = normalize((transform * vec4<f32>(vertex.normal, 0.0)).xyz);
+    out.uv = vertex.uv;
+    out.clip_position = view.view_proj * out.world_position;
+    return out;
+}
+
+@fragment
+fn fragment(
+    @builtin(front_facing) is_front: bool,
+    @builtin(position) frag_coord: vec4<f32>,
+    #import bevy_pbr::mesh_vertex_output
+) -> @location(0) vec4<f32> {
+#ifdef TEXTURED
+    var base_color = textureSample(base_texture, base_texture_sampler, uv);
+#else
+    var base_color = vec4<f32>(0.5, 0.5, 0.5, 1.0);
+#endif
+
+#ifdef ALPHA_MASK
+    if (base_color.a < 0.5) {
+        discard;
+    }
+#endif
+
+    // Same rough dielectric the WMO doodads get from their StandardMaterial.
+    var pbr_input: PbrInput = pbr_input_new();
+    pbr_input.material.base_color = base_color;
+    pbr_input.material.perceptual_roughness = 0.9;
+    pbr_input.material.metallic = 0.0;
+    pbr_input.material.reflectance = 0.1;
+#ifdef ALPHA_BLEND
+    pbr_input.material.flags = STANDARD_MATERIAL_FLAGS_DOUBLE_SIDED_BIT | STANDARD_MATERIAL_FLAGS_ALPHA_MODE_BLEND_BIT;
+#else
+    pbr_input.material.flags = STANDARD_MATERIAL_FLAGS_DOUBLE_SIDED_BIT | STANDARD_MATERIAL_FLAGS_ALPHA_MODE_OPAQUE_BIT;
+#endif
+
+    pbr_input.frag_coord = frag_coord;
+    pbr_input.world_position = world_position;
+    pbr_input.world_normal = world_normal;
+
+    pbr_input.is_orthographic = view.projection[3].w == 1.0;
+
+    pbr_input.N = prepare_normal(
+        pbr_input.material.flags,
+        world_normal,
+        uv,
+        is_front,
+    );
+    pbr_input.V = calculate_view(world_position, pbr_input.is_orthographic);
+
+    return tone_mapping(pbr(pbr_input));
+}
@@ -0,0 +1,21 @@
+#define_import_path forge::fog
+
+// Shared by every material that's fogged. Uses `view`, so `bevy_pbr::mesh_view_bindings` must be imported first.
+
+// Blend towards the fog colour by distance from the camera. `fog_params` holds the mode, start and end.
+fn apply_fog(color: vec4<f32>, world_position: vec3<f32>, fog_color: vec4<f32>, fog_params: vec4<f32>) -> vec4<f32> {
+    if (fog_params.x < 0.5) {
+        return color;
+    }
+
+    let distance = length(world_position - view.world_position.xyz);
+    let linear = clamp((distance - fog_params.y) / (fog_params.z - fog_params.y), 0.0, 1.0);
+
+    var amount: f32 = linear;
+    if (fog_params.x > 1.5) {
+        // Exponential fog, scaled so it's almost opaque by the end distance.
+        amount = 1.0 - exp(-4.0 * linear);
+    }
+
+    return vec4<f32>(mix(color.rgb, fog_color.rgb, amount), color.a);
+}
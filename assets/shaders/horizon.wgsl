@@ -0,0 +1,55 @@
+#import bevy_pbr::mesh_view_bindings
+#import bevy_pbr::mesh_bindings
+
+#import bevy_pbr::pbr_types
+#import bevy_pbr::utils
+#import bevy_pbr::clustered_forward
+#import bevy_pbr::lighting
+#import bevy_pbr::shadows
+#import bevy_pbr::pbr_functions
+
+#import forge::fog
+
+struct HorizonMaterial {
+    color: vec4<f32>,
+    fog_color: vec4<f32>,
+    fog_params: vec4<f32>,
+};
+
+@group(1) @binding(0)
+var<uniform> material: HorizonMaterial;
+
+@fragment
+fn fragment(
+    @builtin(front_facing) is_front: bool,
+    @builtin(position) frag_coord: vec4<f32>,
+    #import bevy_pbr::mesh_vertex_output
+) -> @location(0) vec4<f32> {
+    // A flat, rough ground colour lit like the terrain, so the two meet without a seam in the lighting.
+    var pbr_input: PbrInput = pbr_input_new();
+    pbr_input.material.base_color = material.color;
+    pbr_input.material.perceptual_roughness = 1.0;
+    pbr_input.material.metallic = 0.0;
+    pbr_input.material.reflectance = 0.0;
+
+    pbr_input.frag_coord = frag_coord;
+    pbr_input.world_position = world_position;
+    pbr_input.world_normal = world_normal;
+
+    pbr_input.is_orthographic = view.projection[3].w == 1.0;
+
+    pbr_input.N = prepare_normal(
+        pbr_input.material.flags,
+        world_normal,
+#ifdef VERTEX_TANGENTS
+#ifdef STANDARDMATERIAL_NORMAL_MAP
+        world_tangent,
+#endif
+#endif
+        uv,
+        is_front,
+    );
+    pbr_input.V = calculate_view(world_position, pbr_input.is_orthographic);
+
+    return apply_fog(tone_mapping(pbr(pbr_input)), world_position.xyz, material.fog_color, material.fog_params);
+}
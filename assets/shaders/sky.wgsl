@@ -0,0 +1,54 @@
+#import bevy_pbr::mesh_view_bindings
+#import bevy_pbr::mesh_bindings
+
+struct SkyMaterial {
+    zenith_color: vec4<f32>,
+    horizon_color: vec4<f32>,
+    sun_color: vec4<f32>,
+    sun_direction: vec3<f32>,
+};
+
+@group(1) @binding(0)
+var<uniform> material: SkyMaterial;
+
+struct Vertex {
+    @location(0) position: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) direction: vec3<f32>,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    let world_position = mesh.model * vec4<f32>(vertex.position, 1.0);
+    let clip_position = view.view_proj * world_position;
+
+    var out: VertexOutput;
+    // Depth is reversed and cleared to 0, so a Z of 0 is the far plane: the sky sits behind everything else regardless
+    // of the sphere's size. The pipeline tests with GreaterEqual and doesn't write depth, so it still draws over the clear.
+    out.clip_position = vec4<f32>(clip_position.xy, 0.0, clip_position.w);
+    out.direction = vertex.position;
+    return out;
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    let direction = normalize(in.direction);
+
+    // Gradient from the horizon up, darkening a little below it.
+    let elevation = direction.y;
+    var color: vec3<f32> = mix(material.horizon_color.rgb, material.zenith_color.rgb, sqrt(clamp(elevation, 0.0, 1.0)));
+    if (elevation < 0.0) {
+        color = material.horizon_color.rgb * mix(1.0, 0.6, clamp(-elevation * 4.0, 0.0, 1.0));
+    }
+
+    // A sharp disc for the sun, with a soft glow around it.
+    let sun_dot = max(dot(direction, normalize(material.sun_direction)), 0.0);
+    let disc = smoothstep(0.9995, 0.9998, sun_dot);
+    let glow = pow(sun_dot, 64.0) * 0.4;
+    color = color + material.sun_color.rgb * (disc + glow);
+
+    return vec4<f32>(color, 1.0);
+}
@@ -1,5 +1,28 @@
+#import bevy_pbr::mesh_view_bindings
+#import bevy_pbr::mesh_bindings
+
+#import bevy_pbr::pbr_types
+#import bevy_pbr::utils
+#import bevy_pbr::clustered_forward
+#import bevy_pbr::lighting
+#import bevy_pbr::shadows
+#import bevy_pbr::pbr_functions
+
+#import forge::fog
+
 struct CustomMaterial {
     base_positions: vec2<f32>,
+    specular_layers: vec4<f32>,
+    specular_strength: f32,
+    layer_scales: vec4<f32>,
+    layer_scroll_x: vec4<f32>,
+    layer_scroll_y: vec4<f32>,
+    time: f32,
+    height_scales: vec4<f32>,
+    height_offsets: vec4<f32>,
+    height_blend: f32,
+    fog_color: vec4<f32>,
+    fog_params: vec4<f32>,
 };
 
 @group(1) @binding(0)
@@ -41,16 +64,19 @@ var alpha_4: texture_2d<f32>;
 @group(1) @binding(14)
 var alpha_4_sampler: sampler;
 
-fn saturation(color: vec4<f32>, adjustment: f32) -> vec4<f32>
-{
-    // Algorithm from Chapter 16 of OpenGL Shading Language
-    let W: vec4<f32> = vec4(0.2125, 0.7154, 0.0721, 1.0);
-    let intensity: vec4<f32> = vec4(dot(color, W));
-    return mix(intensity, color, adjustment);
-}
+@group(1) @binding(15)
+var height_1: texture_2d<f32>;
+@group(1) @binding(16)
+var height_2: texture_2d<f32>;
+@group(1) @binding(17)
+var height_3: texture_2d<f32>;
+@group(1) @binding(18)
+var height_4: texture_2d<f32>;
 
 @fragment
 fn fragment(
+    @builtin(front_facing) is_front: bool,
+    @builtin(position) frag_coord: vec4<f32>,
     #import bevy_pbr::mesh_vertex_output
 ) -> @location(0) vec4<f32> {
     let distance_from_origin = uv - material.base_positions.xy;
@@ -58,21 +84,78 @@ fn fragment(
     // For some reason x + y are flipped here, perhaps I made a mistake somewhere.
     let uv_alpha = vec2<f32>(abs(distance_from_origin.y) / 33.333496, abs(distance_from_origin.x) / 33.333496);
 
-    var layer_1_color: vec4<f32> = textureSample(layer_1, layer_1_sampler, uv);
+    // Textures repeat once per chunk unit (1/8th of a chunk), scaled up by each layer's texture flags.
+    let unit_uv = uv / (33.333496 / 8.0);
+
+    // Animated layers (lava, slime) scroll over time. Wrapping the offset keeps the UVs precise.
+    let scroll_x = fract(material.layer_scroll_x * material.time);
+    let scroll_y = fract(material.layer_scroll_y * material.time);
 
-    var layer_2_color: vec4<f32> = textureSample(layer_2, layer_2_sampler, uv);
+    let layer_1_uv = unit_uv / material.layer_scales.x + vec2<f32>(scroll_x.x, scroll_y.x);
+    let layer_2_uv = unit_uv / material.layer_scales.y + vec2<f32>(scroll_x.y, scroll_y.y);
+    let layer_3_uv = unit_uv / material.layer_scales.z + vec2<f32>(scroll_x.z, scroll_y.z);
+    let layer_4_uv = unit_uv / material.layer_scales.w + vec2<f32>(scroll_x.w, scroll_y.w);
+
+    var layer_1_color: vec4<f32> = textureSample(layer_1, layer_1_sampler, layer_1_uv);
+
+    var layer_2_color: vec4<f32> = textureSample(layer_2, layer_2_sampler, layer_2_uv);
     var alpha_2_value: f32 = textureSample(alpha_2, alpha_2_sampler, uv_alpha).r;
 
-    var layer_3_color: vec4<f32> = textureSample(layer_3, layer_3_sampler, uv);
+    var layer_3_color: vec4<f32> = textureSample(layer_3, layer_3_sampler, layer_3_uv);
     var alpha_3_value: f32 = textureSample(alpha_3, alpha_3_sampler, uv_alpha).r;
 
-    var layer_4_color: vec4<f32> = textureSample(layer_4, layer_4_sampler, uv);
+    var layer_4_color: vec4<f32> = textureSample(layer_4, layer_4_sampler, layer_4_uv);
     var alpha_4_value: f32 = textureSample(alpha_4, alpha_4_sampler, uv_alpha).r;
 
     // finalColor = tex0 * (1.0 - (alpha1 + alpha2 + alpha3)) + tex1 * alpha1 + tex2 * alpha2 + tex3 * alpha3
-    var final_color: vec4<f32> = layer_1_color * (1.0 - (alpha_2_value + alpha_3_value + alpha_4_value)) + (layer_2_color * alpha_2_value) + (layer_3_color * alpha_3_value) + (layer_4_color * alpha_4_value);
-
-    // return layer_1_color * (1.0 - (alpha_2_value + alpha_3_value + alpha_4_value)) + (layer_2_color * alpha_2_value);
-
-    return saturation(final_color * (world_normal.y / 2.0), 1.25);
+    var weights = vec4<f32>(1.0 - (alpha_2_value + alpha_3_value + alpha_4_value), alpha_2_value, alpha_3_value, alpha_4_value);
+
+    // Height blending (MTXP): weight each layer by its height, then let the tallest layers win out.
+    if (material.height_blend > 0.5) {
+        let heights = vec4<f32>(
+            textureSample(height_1, layer_1_sampler, layer_1_uv).a,
+            textureSample(height_2, layer_2_sampler, layer_2_uv).a,
+            textureSample(height_3, layer_3_sampler, layer_3_uv).a,
+            textureSample(height_4, layer_4_sampler, layer_4_uv).a,
+        );
+
+        let height_weights = clamp(weights, vec4<f32>(0.0), vec4<f32>(1.0)) * (heights * material.height_scales + material.height_offsets);
+        let max_weight = max(max(height_weights.x, height_weights.y), max(height_weights.z, height_weights.w));
+        let sharpened = height_weights * (1.0 - clamp(max_weight - height_weights, vec4<f32>(0.0), vec4<f32>(1.0)));
+        weights = sharpened / max(dot(sharpened, vec4<f32>(1.0)), 0.00001);
+    }
+    var final_color: vec4<f32> = layer_1_color * weights.x + (layer_2_color * weights.y) + (layer_3_color * weights.z) + (layer_4_color * weights.w);
+
+    // `_s` textures store specular strength in their alpha channel.
+    let layer_speculars = vec4<f32>(layer_1_color.a, layer_2_color.a, layer_3_color.a, layer_4_color.a) * material.specular_layers;
+    let specular = clamp(dot(weights, layer_speculars), 0.0, 1.0) * material.specular_strength;
+
+    // Light the blended colour with Bevy's PBR pipeline, so the sun and ambient lights apply.
+    // Terrain is a rough dielectric, which only gets glossy where a specular layer says so.
+    var pbr_input: PbrInput = pbr_input_new();
+    pbr_input.material.base_color = vec4<f32>(final_color.rgb, 1.0);
+    pbr_input.material.perceptual_roughness = mix(1.0, 0.35, specular);
+    pbr_input.material.metallic = 0.0;
+    pbr_input.material.reflectance = specular * 0.5;
+
+    pbr_input.frag_coord = frag_coord;
+    pbr_input.world_position = world_position;
+    pbr_input.world_normal = world_normal;
+
+    pbr_input.is_orthographic = view.projection[3].w == 1.0;
+
+    pbr_input.N = prepare_normal(
+        pbr_input.material.flags,
+        world_normal,
+#ifdef VERTEX_TANGENTS
+#ifdef STANDARDMATERIAL_NORMAL_MAP
+        world_tangent,
+#endif
+#endif
+        uv,
+        is_front,
+    );
+    pbr_input.V = calculate_view(world_position, pbr_input.is_orthographic);
+
+    return apply_fog(tone_mapping(pbr(pbr_input)), world_position.xyz, material.fog_color, material.fog_params);
 }
@@ -1,6 +1,49 @@
+#import bevy_pbr::mesh_view_bindings
+
+#import forge::fog
+
+struct WaterMaterial {
+    color: vec4<f32>,
+    deep_color: vec4<f32>,
+    fade_depths: vec2<f32>,
+    emissive: vec4<f32>,
+    time: f32,
+    has_texture: f32,
+    fog_color: vec4<f32>,
+    fog_params: vec4<f32>,
+};
+
+@group(1) @binding(0)
+var<uniform> material: WaterMaterial;
+
+@group(1) @binding(1)
+var liquid_texture: texture_2d<f32>;
+@group(1) @binding(2)
+var liquid_sampler: sampler;
+
 @fragment
 fn fragment(
     #import bevy_pbr::mesh_vertex_output
 ) -> @location(0) vec4<f32> {
-    return vec4(0.2, 0.2, 0.6, 0.65);
+    // The mesh stores each vertex's depth above the terrain in its first UV coordinate.
+    let depth = max(uv.x, 0.0);
+
+    var color: vec4<f32> = mix(material.color, material.deep_color, smoothstep(0.0, material.fade_depths.y, depth));
+
+    if (material.has_texture > 0.5) {
+        // Liquid textures repeat once per chunk unit, like the terrain.
+        let liquid_uv = world_position.xz / (33.333496 / 8.0);
+        let sampled = textureSample(liquid_texture, liquid_sampler, liquid_uv);
+        color = vec4<f32>(color.rgb * sampled.rgb * 2.0, color.a);
+    } else {
+        // Procedural ripples for when the client's liquid textures aren't available.
+        let ripple = sin(world_position.x * 0.35 + material.time * 1.3) * sin(world_position.z * 0.3 - material.time * 1.1);
+        color = vec4<f32>(color.rgb * (1.0 + 0.12 * ripple), color.a);
+    }
+
+    // Fade out towards the shoreline, where the liquid meets the terrain.
+    let shore_fade = smoothstep(0.0, material.fade_depths.x, depth);
+
+    let lit = vec4<f32>(color.rgb + material.emissive.rgb, color.a * shore_fade);
+    return apply_fog(lit, world_position.xyz, material.fog_color, material.fog_params);
 }
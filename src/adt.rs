@@ -0,0 +1,354 @@
+//! ADT data that wow_chunky doesn't expose, read straight from the tile's files.
+
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::{Vec2, Vec3};
+
+use crate::coordinates::ADTPosition;
+use crate::liquid::ChunkLiquid;
+use crate::raw;
+
+/// MTXF/MTXP flag bits holding the texture's tiling scale, as a power of two.
+const TEXTURE_SCALE_MASK: u32 = 0xF0;
+const TEXTURE_SCALE_SHIFT: u32 = 4;
+/// MTXF/MTXP flag telling the client to skip a texture's `_s` and `_h` variants.
+const TEXTURE_NO_EXTRA_MAPS: u32 = 0x01;
+
+/// MCLY flag bits describing a layer's scrolling animation.
+const LAYER_ANIMATION_ROTATION_MASK: u32 = 0x07;
+const LAYER_ANIMATION_SPEED_SHIFT: u32 = 3;
+const LAYER_ANIMATION_SPEED_MASK: u32 = 0x07;
+const LAYER_ANIMATION_ENABLED: u32 = 0x40;
+
+/// Texture repeats per second at the fastest animation speed.
+const LAYER_ANIMATION_MAX_SPEED: f32 = 0.625;
+
+/// MCNK header fields, as offsets into the chunk data.
+const MCNK_HEADER_SIZE: usize = 0x80;
+const MCNK_FLAGS: usize = 0x00;
+const MCNK_LAYER_COUNT: usize = 0x0C;
+const MCNK_LAYER_OFFSET: usize = 0x1C;
+const MCNK_AREA_ID: usize = 0x34;
+const MCNK_LIQUID_OFFSET: usize = 0x60;
+const MCNK_POSITION: usize = 0x68;
+
+const MCLY_ENTRY_SIZE: usize = 16;
+
+/// Number of MCNKs in a tile.
+const ADT_CHUNKS: usize = 16 * 16;
+
+const MDDF_ENTRY_SIZE: usize = 36;
+/// MDDF flag marking the name ID as a FileDataID (Legion+), which we can't resolve to a path.
+const MDDF_NAME_IS_FILE_ID: u16 = 0x40;
+
+const MODF_ENTRY_SIZE: usize = 64;
+/// MODF flags for a scale being present (Legion+, zero before then), and the name ID being a FileDataID.
+const MODF_HAS_SCALE: u16 = 0x04;
+const MODF_NAME_IS_FILE_ID: u16 = 0x08;
+
+#[derive(Debug, Clone, Default)]
+pub struct AdtExtras {
+    /// Per-texture flags from MTXF, or MTXP in split (Cataclysm+) files. Indexed like MTEX.
+    pub texture_flags: Vec<u32>,
+    /// Per-texture height blending scale and offset from MTXP. Empty when the tile has no MTXP.
+    pub texture_heights: Vec<(f32, f32)>,
+    /// Per-chunk data, in the same order as the tile's MCNKs.
+    pub chunks: Vec<ChunkExtras>,
+    /// M2 doodads placed on the tile by MDDF.
+    pub doodads: Vec<DoodadPlacement>,
+    /// WMOs placed on the tile by MODF.
+    pub wmos: Vec<WmoPlacement>,
+}
+
+/// A single MDDF entry, with its filename resolved through MMID/MMDX.
+#[derive(Debug, Clone)]
+pub struct DoodadPlacement {
+    /// Client path of the model, e.g. `World\Azeroth\Elwynn\Tree01.m2`.
+    pub filename: String,
+    /// Identifies the placement across tiles, as doodads near a border are listed by every tile they touch.
+    pub unique_id: u32,
+    /// Position in placement coordinates, see `coordinates::placement_transform`.
+    pub position: Vec3,
+    /// Rotation in degrees around each placement axis.
+    pub rotation: Vec3,
+    pub scale: f32,
+}
+
+/// A single MODF entry, with its filename resolved through MWID/MWMO.
+#[derive(Debug, Clone)]
+pub struct WmoPlacement {
+    /// Client path of the root WMO, e.g. `World\wmo\Azeroth\Buildings\Stormwind\Stormwind.wmo`.
+    pub filename: String,
+    /// Identifies the placement across tiles, as WMOs are listed by every tile they overlap.
+    pub unique_id: u32,
+    /// Position in placement coordinates, see `coordinates::placement_transform`.
+    pub position: Vec3,
+    /// Rotation in degrees around each placement axis.
+    pub rotation: Vec3,
+    /// Minimum and maximum corners of the placed WMO's bounds, in placement coordinates.
+    pub extents: (Vec3, Vec3),
+    /// Doodad set to show on top of the default set.
+    pub doodad_set: u16,
+    pub scale: f32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ChunkExtras {
+    /// Raw MCNK header flags.
+    pub flags: u32,
+    /// AreaTable ID of the zone or subzone the chunk is in.
+    pub area_id: u32,
+    /// MCLY flags for each texture layer.
+    pub layer_flags: Vec<u32>,
+    /// Liquid surfaces over the chunk.
+    pub liquids: Vec<ChunkLiquid>,
+}
+
+impl ChunkExtras {
+    /// Read an MCNK from a root ADT, where sub-chunks are found through the header's offsets.
+    fn read(&mut self, data: &[u8]) {
+        self.flags = raw::read_u32(data, MCNK_FLAGS).unwrap_or(0);
+        self.area_id = raw::read_u32(data, MCNK_AREA_ID).unwrap_or(0);
+
+        // MCLQ's own chunk header often has a bogus size, so read from just past it instead of trusting it.
+        if let Some(offset) = raw::read_u32(data, MCNK_LIQUID_OFFSET) {
+            if offset as usize >= MCNK_HEADER_SIZE + 8 {
+                if let Some(mclq) = data.get(offset as usize..) {
+                    self.liquids = ChunkLiquid::from_mclq(mclq, self.flags);
+                }
+            }
+        }
+
+        let layer_count = raw::read_u32(data, MCNK_LAYER_COUNT).unwrap_or(0) as usize;
+        if let Some(mcly) = raw::read_u32(data, MCNK_LAYER_OFFSET).and_then(|offset| sub_chunk_at(data, offset)) {
+            self.read_layers(mcly, layer_count);
+        }
+    }
+
+    /// Read an MCNK from a split `_tex0` file, which has no header and stores sub-chunks back to back.
+    fn read_split(&mut self, data: &[u8]) {
+        for (magic, chunk) in raw::chunks(data) {
+            if &magic == b"MCLY" {
+                self.read_layers(chunk, chunk.len() / MCLY_ENTRY_SIZE);
+            }
+        }
+    }
+
+    fn read_layers(&mut self, mcly: &[u8], layer_count: usize) {
+        self.layer_flags = mcly.chunks_exact(MCLY_ENTRY_SIZE)
+            .take(layer_count)
+            .filter_map(|entry| raw::read_u32(entry, 4))
+            .collect();
+    }
+
+    /// Scrolling velocity of a layer in texture repeats per second, or zero if it's static.
+    pub fn layer_scroll(&self, layer: usize) -> Vec2 {
+        // Directions for each 45° step of the rotation bits, in texture space.
+        const DIRECTIONS: [(f32, f32); 8] = [
+            (0.0, 1.0), (-1.0, 1.0), (-1.0, 0.0), (-1.0, -1.0),
+            (0.0, -1.0), (1.0, -1.0), (1.0, 0.0), (1.0, 1.0),
+        ];
+
+        let flags = self.layer_flags.get(layer).copied().unwrap_or(0);
+        if flags & LAYER_ANIMATION_ENABLED == 0 {
+            return Vec2::ZERO
+        }
+
+        let (x, y) = DIRECTIONS[(flags & LAYER_ANIMATION_ROTATION_MASK) as usize];
+        let speed = ((flags >> LAYER_ANIMATION_SPEED_SHIFT) & LAYER_ANIMATION_SPEED_MASK) as f32 / 7.0;
+
+        Vec2::new(x, y) * speed * LAYER_ANIMATION_MAX_SPEED
+    }
+}
+
+impl AdtExtras {
+    /// Read the extras for an ADT, including its `_tex0` file when the tile is split.
+    /// Missing or malformed files leave the relevant fields empty.
+    pub fn from_file(path: &Path) -> Self {
+        let mut extras = Self::default();
+
+        if let Ok(data) = std::fs::read(path) {
+            extras.read_chunks(&data, false);
+        }
+        if let Ok(data) = std::fs::read(split_path(path, "tex0")) {
+            extras.read_chunks(&data, true);
+        }
+        if let Ok(data) = std::fs::read(split_path(path, "obj0")) {
+            extras.read_chunks(&data, true);
+        }
+
+        extras
+    }
+
+    fn read_chunks(&mut self, data: &[u8], split: bool) {
+        let mut mcnk_index = 0;
+        let mut mh2o = None;
+        let mut mmdx: &[u8] = &[];
+        let mut mmid: &[u8] = &[];
+        let mut mddf: &[u8] = &[];
+        let mut mwmo: &[u8] = &[];
+        let mut mwid: &[u8] = &[];
+        let mut modf: &[u8] = &[];
+
+        for (magic, chunk) in raw::chunks(data) {
+            match &magic {
+                b"MCNK" => {
+                    if self.chunks.len() <= mcnk_index {
+                        self.chunks.resize(mcnk_index + 1, ChunkExtras::default());
+                    }
+
+                    if split {
+                        self.chunks[mcnk_index].read_split(chunk);
+                    } else {
+                        self.chunks[mcnk_index].read(chunk);
+                    }
+                    mcnk_index += 1;
+                }
+                b"MH2O" => mh2o = Some(chunk),
+                b"MMDX" => mmdx = chunk,
+                b"MMID" => mmid = chunk,
+                b"MDDF" => mddf = chunk,
+                b"MWMO" => mwmo = chunk,
+                b"MWID" => mwid = chunk,
+                b"MODF" => modf = chunk,
+                b"MTXF" => {
+                    self.texture_flags = chunk.chunks_exact(4)
+                        .filter_map(|entry| raw::read_u32(entry, 0))
+                        .collect();
+                }
+                b"MTXP" => {
+                    self.texture_flags = chunk.chunks_exact(16)
+                        .filter_map(|entry| raw::read_u32(entry, 0))
+                        .collect();
+                    self.texture_heights = chunk.chunks_exact(16)
+                        .filter_map(|entry| Some((raw::read_f32(entry, 4)?, raw::read_f32(entry, 8)?)))
+                        .collect();
+                }
+                _ => {}
+            }
+        }
+
+        // Only WotLK and later tiles have MH2O, stored before the MCNKs it describes, so it's applied once they've all been read.
+        // A chunk's MCLQ liquid is only replaced when MH2O has instances for that chunk, otherwise it's kept.
+        if let Some(mh2o) = mh2o {
+            for index in 0..ADT_CHUNKS {
+                let liquids = ChunkLiquid::from_mh2o(mh2o, index);
+                if liquids.is_empty() {
+                    continue;
+                }
+
+                if self.chunks.len() <= index {
+                    self.chunks.resize(index + 1, ChunkExtras::default());
+                }
+                self.chunks[index].liquids = liquids;
+            }
+        }
+
+        if !mddf.is_empty() {
+            self.doodads = read_placements(mddf, MDDF_ENTRY_SIZE, mmdx, mmid)
+                .filter_map(|(filename, entry)| {
+                    if raw::read_u16(entry, 34)? & MDDF_NAME_IS_FILE_ID != 0 {
+                        return None
+                    }
+
+                    Some(DoodadPlacement {
+                        filename,
+                        unique_id: raw::read_u32(entry, 4)?,
+                        position: raw::read_vec3(entry, 8)?,
+                        rotation: raw::read_vec3(entry, 20)?,
+                        scale: raw::read_u16(entry, 32)? as f32 / 1024.0,
+                    })
+                })
+                .collect();
+        }
+
+        if !modf.is_empty() {
+            self.wmos = read_placements(modf, MODF_ENTRY_SIZE, mwmo, mwid)
+                .filter_map(|(filename, entry)| {
+                    let flags = raw::read_u16(entry, 56)?;
+                    if flags & MODF_NAME_IS_FILE_ID != 0 {
+                        return None
+                    }
+
+                    let scale = if flags & MODF_HAS_SCALE != 0 {
+                        raw::read_u16(entry, 62)? as f32 / 1024.0
+                    } else {
+                        1.0
+                    };
+
+                    Some(WmoPlacement {
+                        filename,
+                        unique_id: raw::read_u32(entry, 4)?,
+                        position: raw::read_vec3(entry, 8)?,
+                        rotation: raw::read_vec3(entry, 20)?,
+                        extents: (raw::read_vec3(entry, 32)?, raw::read_vec3(entry, 44)?),
+                        doodad_set: raw::read_u16(entry, 58)?,
+                        scale,
+                    })
+                })
+                .collect();
+        }
+    }
+
+    /// How many chunk units one repeat of a texture covers, from its scale flags.
+    pub fn texture_scale(&self, texture_id: usize) -> f32 {
+        let flags = self.texture_flags.get(texture_id).copied().unwrap_or(0);
+        (1 << ((flags & TEXTURE_SCALE_MASK) >> TEXTURE_SCALE_SHIFT)) as f32
+    }
+
+    /// Height blending scale and offset for a texture, if MTXP provides them and its `_h` texture should be used.
+    pub fn texture_height(&self, texture_id: usize) -> Option<(f32, f32)> {
+        let flags = self.texture_flags.get(texture_id).copied().unwrap_or(0);
+        if flags & TEXTURE_NO_EXTRA_MAPS != 0 {
+            return None
+        }
+
+        self.texture_heights.get(texture_id).copied()
+    }
+}
+
+/// Find a sub-chunk from an offset in the MCNK header, which counts from the start of the MCNK's own chunk header.
+fn sub_chunk_at(data: &[u8], offset: u32) -> Option<&[u8]> {
+    let offset = (offset as usize).checked_sub(8)?;
+    if offset < MCNK_HEADER_SIZE {
+        return None
+    }
+
+    raw::chunks(data.get(offset..)?).next().map(|(_, chunk)| chunk)
+}
+
+/// Pair each placement entry with its model's filename, looked up through the offsets in `ids` (MMID/MWID).
+fn read_placements<'a>(entries: &'a [u8], entry_size: usize, names: &'a [u8], ids: &'a [u8]) -> impl Iterator<Item = (String, &'a [u8])> + 'a {
+    entries.chunks_exact(entry_size).filter_map(move |entry| {
+        let name_offset = raw::read_u32(ids, raw::read_u32(entry, 0)? as usize * 4)?;
+        let filename = raw::read_cstring(names, name_offset as usize)?;
+
+        Some((filename, entry))
+    })
+}
+
+/// Path of a tile's root ADT, next to the map's WDT.
+pub fn tile_path(wdt_path: &Path, position: &ADTPosition) -> PathBuf {
+    let map_name = wdt_path.file_stem().and_then(|name| name.to_str()).unwrap_or_default();
+    wdt_path.with_file_name(format!("{}_{}_{}.adt", map_name, position.x, position.y))
+}
+
+/// Corner position and area ID of each MCNK in a root ADT, without reading anything else.
+/// Empty if the tile doesn't exist.
+pub fn read_chunk_areas(path: &Path) -> Vec<(Vec3, u32)> {
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(_) => return Vec::new(),
+    };
+
+    raw::chunks(&data)
+        .filter(|(magic, _)| magic == b"MCNK")
+        .filter_map(|(_, chunk)| Some((raw::read_vec3(chunk, MCNK_POSITION)?, raw::read_u32(chunk, MCNK_AREA_ID)?)))
+        .collect()
+}
+
+/// Path to one of a split ADT's companion files, e.g. `Azeroth_32_48_tex0.adt`.
+fn split_path(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+    path.with_file_name(format!("{}_{}.adt", stem, suffix))
+}
@@ -101,3 +101,37 @@ impl From<Vec3> for WorldPosition {
         }
     }
 }
+
+/// Offset between placement coordinates (MDDF/MODF) and world coordinates.
+pub static PLACEMENT_OFFSET: f32 = 17_066.666;
+
+/// Convert a point in placement coordinates to Bevy's.
+/// Placements are Y up, with X and Z measured from the map's corner rather than its centre.
+pub fn placement_to_bevy(position: Vec3) -> Vec3 {
+    Vec3::new(
+        PLACEMENT_OFFSET - position.z,
+        position.y,
+        PLACEMENT_OFFSET - position.x,
+    )
+}
+
+/// Build the Bevy transform for an MDDF/MODF placement.
+/// The model's own geometry is expected to already have its Y and Z axes swapped, like the terrain.
+pub fn placement_transform(position: Vec3, rotation: Vec3, scale: f32) -> Transform {
+    let translation = placement_to_bevy(position);
+
+    // The client applies the rotations around its placement axes; these are the same rotations in WoW's axes.
+    let wow_rotation = Quat::from_rotation_z((rotation.y - 90.0).to_radians())
+        * Quat::from_rotation_x(rotation.x.to_radians())
+        * Quat::from_rotation_y(-rotation.z.to_radians())
+        * Quat::from_rotation_z(-90_f32.to_radians());
+
+    // Swapping Y and Z mirrors the rotation's axis and reverses its direction.
+    let rotation = Quat::from_xyzw(-wow_rotation.x, -wow_rotation.z, -wow_rotation.y, wow_rotation.w);
+
+    Transform {
+        translation,
+        rotation,
+        scale: Vec3::splat(scale),
+    }
+}
@@ -0,0 +1,466 @@
+//! Client database tables, read from WDBC (`.dbc`) or WDB2 (`.db2`) files.
+//! The typed rows use the WotLK (3.3.5) layouts.
+
+use std::path::{Path, PathBuf};
+
+use bevy::{prelude::*, utils::hashbrown::HashMap};
+
+use wow_chunky::files;
+
+use crate::raw;
+
+const WDBC_HEADER_SIZE: usize = 20;
+const WDB2_HEADER_SIZE: usize = 48;
+/// WDB2 files up to this build stop their header after the build number, without the ID range or its index.
+const WDB2_LAST_SHORT_HEADER_BUILD: u32 = 12880;
+const WDB2_SHORT_HEADER_SIZE: usize = 28;
+
+/// A WDBC or WDB2 file: fixed size records of 4 byte fields, followed by a block of strings they point into.
+pub struct Dbc {
+    records_offset: usize,
+    record_count: usize,
+    record_size: usize,
+    field_count: usize,
+    data: Vec<u8>,
+}
+
+/// One record of a table, read by field index.
+#[derive(Clone, Copy)]
+pub struct Record<'a> {
+    data: &'a [u8],
+    strings: &'a [u8],
+}
+
+impl Dbc {
+    pub fn from_file(path: &Path) -> Option<Self> {
+        Self::from_bytes(std::fs::read(path).ok()?)
+    }
+
+    fn from_bytes(data: Vec<u8>) -> Option<Self> {
+        let records_offset = match data.get(0..4)? {
+            b"WDBC" => WDBC_HEADER_SIZE,
+            b"WDB2" if raw::read_u32(&data, 0x18)? <= WDB2_LAST_SHORT_HEADER_BUILD => WDB2_SHORT_HEADER_SIZE,
+            b"WDB2" => {
+                // Newer WDB2 files index their rows by ID after the header, with a u32 and a u16 per ID in range.
+                let min_id = raw::read_u32(&data, 0x20)? as usize;
+                let max_id = raw::read_u32(&data, 0x24)? as usize;
+                if max_id == 0 {
+                    WDB2_HEADER_SIZE
+                } else {
+                    WDB2_HEADER_SIZE + (max_id.checked_sub(min_id)? + 1) * 6
+                }
+            },
+            _ => return None,
+        };
+
+        let record_count = raw::read_u32(&data, 0x04)? as usize;
+        let field_count = raw::read_u32(&data, 0x08)? as usize;
+        let record_size = raw::read_u32(&data, 0x0C)? as usize;
+        let string_size = raw::read_u32(&data, 0x10)? as usize;
+        if record_size == 0 || data.len() < records_offset + record_count * record_size + string_size {
+            return None
+        }
+
+        Some(Self { records_offset, record_count, record_size, field_count, data })
+    }
+
+    /// Open a table from the extracted client data, preferring `.dbc` over `.db2`.
+    pub fn open(name: &str) -> Option<Self> {
+        ["dbc", "db2"].iter()
+            .find_map(|extension| Self::from_file(&table_path(name).with_extension(extension)))
+    }
+
+    /// Number of fields per record. Tables gain and lose fields between expansions, so this tells layouts apart.
+    pub fn field_count(&self) -> usize {
+        self.field_count
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = Record<'_>> {
+        let records_end = self.records_offset + self.record_count * self.record_size;
+        let strings = &self.data[records_end..];
+
+        self.data[self.records_offset..records_end]
+            .chunks_exact(self.record_size)
+            .map(move |data| Record { data, strings })
+    }
+}
+
+impl<'a> Record<'a> {
+    pub fn u32(&self, field: usize) -> u32 {
+        raw::read_u32(self.data, field * 4).unwrap_or(0)
+    }
+
+    pub fn f32(&self, field: usize) -> f32 {
+        raw::read_f32(self.data, field * 4).unwrap_or(0.0)
+    }
+
+    /// A string field, stored as an offset into the string block.
+    pub fn string(&self, field: usize) -> String {
+        raw::read_cstring(self.strings, self.u32(field) as usize).unwrap_or_default()
+    }
+}
+
+/// Path to a table in the extracted client data, without its extension.
+fn table_path(name: &str) -> PathBuf {
+    PathBuf::from(format!("./test_data/DBFilesClient/{}", name))
+}
+
+/// A typed row of a client table.
+pub trait DbcRow: Sized {
+    /// Name of the table's file, without its extension.
+    const NAME: &'static str;
+    /// Fields per record in the layout `from_record` reads. Tables with any other count are from another expansion.
+    const FIELD_COUNT: usize;
+
+    fn from_record(record: &Record) -> Self;
+    fn id(&self) -> u32;
+}
+
+/// Every row of a client table, by ID. Inserted as a resource for systems to look rows up in.
+/// Empty if the table isn't in the test data or has another layout, so lookups fall back to whatever the caller did without it.
+pub struct DbcTable<T> {
+    rows: HashMap<u32, T>,
+}
+
+impl<T: DbcRow> DbcTable<T> {
+    pub fn load() -> Self {
+        let rows = Dbc::open(T::NAME)
+            .and_then(|dbc| Self::rows(&dbc))
+            .unwrap_or_default();
+
+        Self { rows }
+    }
+
+    /// Read every row, unless the table's field count doesn't match the layout `T` reads.
+    fn rows(dbc: &Dbc) -> Option<HashMap<u32, T>> {
+        if dbc.field_count() != T::FIELD_COUNT {
+            warn!("{} has {} fields per record, expected {}, ignoring it", T::NAME, dbc.field_count(), T::FIELD_COUNT);
+            return None
+        }
+
+        Some(dbc.records().map(|record| T::from_record(&record)).map(|row| (row.id(), row)).collect())
+    }
+
+    pub fn get(&self, id: u32) -> Option<&T> {
+        self.rows.get(&id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.rows.values()
+    }
+}
+
+/// A row of Map.dbc.
+#[derive(Debug, Clone)]
+pub struct MapRecord {
+    pub id: u32,
+    /// Folder the map's WDT and ADTs live in, e.g. `Azeroth`.
+    pub directory: String,
+    /// English name, e.g. `Eastern Kingdoms`.
+    pub name: String,
+}
+
+impl DbcRow for MapRecord {
+    const NAME: &'static str = "Map";
+    const FIELD_COUNT: usize = 66;
+
+    fn from_record(record: &Record) -> Self {
+        Self {
+            id: record.u32(0),
+            directory: record.string(1),
+            name: record.string(5),
+        }
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+/// The map being viewed, found by matching the WDT's name against each map's directory.
+pub fn current_map<'a>(maps: &'a DbcTable<MapRecord>, wdt: &files::WDT) -> Option<&'a MapRecord> {
+    let name = wdt.path.file_stem()?.to_str()?;
+    maps.iter().find(|map| map.directory.eq_ignore_ascii_case(name))
+}
+
+/// A row of AreaTable.dbc: a zone, or a subzone if it has a parent.
+#[derive(Debug, Clone)]
+pub struct AreaRecord {
+    pub id: u32,
+    pub map_id: u32,
+    /// Zone containing this subzone, or 0 for a zone.
+    pub parent_id: u32,
+    /// English name, e.g. `Elwynn Forest`.
+    pub name: String,
+}
+
+impl DbcRow for AreaRecord {
+    const NAME: &'static str = "AreaTable";
+    const FIELD_COUNT: usize = 36;
+
+    fn from_record(record: &Record) -> Self {
+        Self {
+            id: record.u32(0),
+            map_id: record.u32(1),
+            parent_id: record.u32(2),
+            name: record.string(11),
+        }
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+impl DbcTable<AreaRecord> {
+    /// Name of an area with its zone, e.g. `Elwynn Forest: Goldshire`, or the raw ID if it isn't in the table.
+    pub fn full_name(&self, id: u32) -> String {
+        let area = match self.get(id) {
+            Some(area) => area,
+            None => return format!("Area {}", id),
+        };
+
+        match self.get(area.parent_id) {
+            Some(zone) if area.parent_id != 0 => format!("{}: {}", zone.name, area.name),
+            _ => area.name.clone(),
+        }
+    }
+}
+
+/// A row of LiquidType.dbc, the type of an MH2O liquid instance.
+#[derive(Debug, Clone)]
+pub struct LiquidTypeRecord {
+    pub id: u32,
+    /// e.g. `Slow Water` or `Naxxramas - Slime`.
+    pub name: String,
+    /// Basic kind of liquid: 0 water, 1 ocean, 2 magma, 3 slime.
+    pub sound_bank: u32,
+}
+
+impl DbcRow for LiquidTypeRecord {
+    const NAME: &'static str = "LiquidType";
+    const FIELD_COUNT: usize = 45;
+
+    fn from_record(record: &Record) -> Self {
+        Self {
+            id: record.u32(0),
+            name: record.string(1),
+            sound_bank: record.u32(3),
+        }
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+/// A row of Light.dbc, an area of a map with its own sky and lighting.
+#[derive(Debug, Clone)]
+pub struct LightRecord {
+    pub id: u32,
+    pub map_id: u32,
+    /// Centre of the light, in placement coordinates (the file stores them multiplied by 36).
+    pub position: Vec3,
+    /// Distance within which the light applies fully, and at which it has faded out entirely.
+    pub falloff_start: f32,
+    pub falloff_end: f32,
+    /// LightParams IDs for clear weather, underwater, storms and so on. Only the first is used.
+    pub params: [u32; 8],
+}
+
+impl LightRecord {
+    /// Each map has one light at the origin with no falloff, used wherever no other light reaches.
+    pub fn is_global(&self) -> bool {
+        self.position == Vec3::ZERO && self.falloff_end == 0.0
+    }
+}
+
+/// Light.dbc positions and distances are stored in 1/36th yards.
+const LIGHT_SCALE: f32 = 36.0;
+
+impl DbcRow for LightRecord {
+    const NAME: &'static str = "Light";
+    const FIELD_COUNT: usize = 15;
+
+    fn from_record(record: &Record) -> Self {
+        let mut params = [0; 8];
+        for (i, param) in params.iter_mut().enumerate() {
+            *param = record.u32(7 + i);
+        }
+
+        Self {
+            id: record.u32(0),
+            map_id: record.u32(1),
+            position: Vec3::new(record.f32(2), record.f32(3), record.f32(4)) / LIGHT_SCALE,
+            falloff_start: record.f32(5) / LIGHT_SCALE,
+            falloff_end: record.f32(6) / LIGHT_SCALE,
+            params,
+        }
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+/// Keyframes for a value over the day, from LightIntBand or LightFloatBand.
+/// Times are in half minutes since midnight, from 0 to 2880.
+#[derive(Debug, Clone)]
+pub struct LightBand<T> {
+    pub times: Vec<u32>,
+    pub values: Vec<T>,
+}
+
+/// Half minutes in a day, the unit of light band times.
+pub const LIGHT_BAND_DAY: u32 = 2880;
+
+impl<T: Copy> LightBand<T> {
+    /// Interpolate the value at a time of day, wrapping from the last keyframe back round to the first.
+    pub fn sample(&self, time: u32, lerp: impl Fn(T, T, f32) -> T) -> Option<T> {
+        let count = self.times.len().min(self.values.len());
+        if count == 0 {
+            return None
+        }
+
+        let time = time % LIGHT_BAND_DAY;
+        let next = self.times[..count].iter().position(|t| *t > time).unwrap_or(count);
+        let (from, to) = if next == 0 || next == count {
+            (count - 1, 0)
+        } else {
+            (next - 1, next)
+        };
+
+        let span = (self.times[to] + LIGHT_BAND_DAY - self.times[from]) % LIGHT_BAND_DAY;
+        let elapsed = (time + LIGHT_BAND_DAY - self.times[from]) % LIGHT_BAND_DAY;
+        let t = if span == 0 { 0.0 } else { elapsed as f32 / span as f32 };
+
+        Some(lerp(self.values[from], self.values[to], t))
+    }
+}
+
+/// LightIntBand rows: ID, keyframe count, then 16 times and 16 values.
+const INT_BAND_FIELD_COUNT: usize = 34;
+
+/// Read LightIntBand, which has up to 16 keyframes per row.
+/// Colours are stored as 0x00RRGGBB.
+pub fn read_int_bands() -> HashMap<u32, LightBand<Color>> {
+    let dbc = match Dbc::open("LightIntBand") {
+        Some(dbc) => dbc,
+        None => return HashMap::new(),
+    };
+    if dbc.field_count() != INT_BAND_FIELD_COUNT {
+        warn!("LightIntBand has {} fields per record, expected {}, ignoring it", dbc.field_count(), INT_BAND_FIELD_COUNT);
+        return HashMap::new()
+    }
+
+    dbc.records()
+        .map(|record| {
+            let count = (record.u32(1) as usize).min(16);
+            let band = LightBand {
+                times: (0..count).map(|i| record.u32(2 + i)).collect(),
+                values: (0..count)
+                    .map(|i| {
+                        let [b, g, r, _] = record.u32(18 + i).to_le_bytes();
+                        Color::rgb_u8(r, g, b)
+                    })
+                    .collect(),
+            };
+
+            (record.u32(0), band)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An ID and a name, the smallest table worth reading.
+    struct NameRecord {
+        id: u32,
+        name: String,
+    }
+
+    impl DbcRow for NameRecord {
+        const NAME: &'static str = "Names";
+        const FIELD_COUNT: usize = 2;
+
+        fn from_record(record: &Record) -> Self {
+            Self {
+                id: record.u32(0),
+                name: record.string(1),
+            }
+        }
+
+        fn id(&self) -> u32 {
+            self.id
+        }
+    }
+
+    const RECORDS: [[u32; 2]; 2] = [[1, 1], [2, 9]];
+    const STRINGS: &[u8] = b"\0Azeroth\0Kalimdor\0";
+
+    /// A table of `RECORDS`, with the header fields after the magic and anything between the header and the records.
+    fn table(magic: &[u8], header: &[u32], index: &[u8]) -> Vec<u8> {
+        let mut data = magic.to_vec();
+        data.extend(header.iter().flat_map(|field| field.to_le_bytes()));
+        data.extend(index);
+        data.extend(RECORDS.iter().flatten().flat_map(|field| field.to_le_bytes()));
+        data.extend(STRINGS);
+
+        data
+    }
+
+    /// Record count, field count, record size and string block size, shared by both formats.
+    fn counts(field_count: u32) -> [u32; 4] {
+        [RECORDS.len() as u32, field_count, 8, STRINGS.len() as u32]
+    }
+
+    fn names(data: Vec<u8>) -> Option<HashMap<u32, String>> {
+        let dbc = Dbc::from_bytes(data)?;
+        let rows = DbcTable::<NameRecord>::rows(&dbc)?;
+
+        Some(rows.into_iter().map(|(id, row)| (id, row.name)).collect())
+    }
+
+    fn assert_names(names: Option<HashMap<u32, String>>) {
+        let names = names.expect("table should load");
+        assert_eq!(names.len(), 2);
+        assert_eq!(names[&1], "Azeroth");
+        assert_eq!(names[&2], "Kalimdor");
+    }
+
+    #[test]
+    fn wdbc() {
+        assert_names(names(table(b"WDBC", &counts(2), &[])));
+    }
+
+    #[test]
+    fn wdbc_with_another_field_count() {
+        let data = table(b"WDBC", &counts(3), &[]);
+        assert_eq!(Dbc::from_bytes(data.clone()).map(|dbc| dbc.field_count()), Some(3));
+        assert!(names(data).is_none());
+    }
+
+    #[test]
+    fn wdb2_short_header() {
+        // Table hash and build, and nothing else.
+        let header = [&counts(2)[..], &[0, 12340]].concat();
+        assert_names(names(table(b"WDB2", &header, &[])));
+    }
+
+    #[test]
+    fn wdb2_without_index() {
+        // Table hash, build, timestamp, min and max ID, locale and copy table size.
+        let header = [&counts(2)[..], &[0, 15595, 0, 0, 0, 0, 0]].concat();
+        assert_names(names(table(b"WDB2", &header, &[])));
+    }
+
+    #[test]
+    fn wdb2_with_index() {
+        let header = [&counts(2)[..], &[0, 15595, 0, 1, 2, 0, 0]].concat();
+        // A row index (u32) and string length (u16) for each of IDs 1 and 2, which the reader only skips over.
+        let index = [0; 12];
+        assert_names(names(table(b"WDB2", &header, &index)));
+    }
+}
@@ -0,0 +1,369 @@
+//! M2 doodads placed by a tile's MDDF entries, and the cache of models shared with WMO doodad sets.
+//! A tile's placements of a model are drawn with GPU instancing, see `instancing`.
+
+use std::path::PathBuf;
+
+use bevy::{prelude::*, render::view::NoFrustumCulling, tasks::{AsyncComputeTaskPool, Task}, utils::hashbrown::{HashMap, HashSet}};
+use futures_lite::future;
+
+use crate::adt::AdtExtras;
+use crate::coordinates::{self, ADTPosition};
+use crate::instancing::DoodadInstances;
+use crate::m2::{M2Blend, M2Model};
+use crate::textures::{self, TextureSettings};
+use crate::placeholders::Placeholder;
+use crate::wmo::Wmo;
+
+/// Mesh and material for each batch of a model, shared by every placement of it.
+pub type ModelParts = Vec<(Handle<Mesh>, Handle<StandardMaterial>)>;
+
+/// One batch of a cached model. WMO doodads draw it with `material`, ADT doodads are instanced with `texture` and `blend`.
+#[derive(Clone)]
+pub struct ModelBatch {
+    pub mesh: Handle<Mesh>,
+    pub material: Handle<StandardMaterial>,
+    pub texture: Option<Handle<Image>>,
+    pub blend: M2Blend,
+}
+
+/// Marker for entities drawing an M2 doodad.
+#[derive(Component)]
+pub struct Doodad;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectSettings {
+    pub doodads: bool,
+    pub wmos: bool,
+    /// Load and draw the models themselves. Tiles loaded while this is off get their models once it's back on.
+    pub models: bool,
+    /// Draw placements as wireframe boxes. Like `models`, tiles loaded while this is off get their boxes once it is on.
+    pub bounding_boxes: bool,
+}
+
+impl Default for ObjectSettings {
+    fn default() -> Self {
+        Self {
+            doodads: true,
+            wmos: true,
+            models: true,
+            bounding_boxes: false,
+        }
+    }
+}
+
+/// An M2 read from disk along with those of its textures that weren't cached yet, ready to be turned into assets.
+/// Textures that failed to load are kept as `None`.
+pub type LoadedModel = (M2Model, Vec<(String, Option<Image>)>);
+
+/// An M2 being read on the `AsyncComputeTaskPool`, like `AdtParsingTask` for tiles.
+#[derive(Component)]
+pub struct ModelLoadingTask(String, Task<Option<LoadedModel>>);
+
+/// Read an M2 and any of its textures not in `cached_textures`, without touching any assets so it can run off the main thread.
+fn load_model(filename: &str, cached_textures: &HashSet<String>, texture_settings: &TextureSettings) -> Option<LoadedModel> {
+    let model = M2Model::from_file(&model_path(filename))?;
+
+    let mut loaded_textures: Vec<(String, Option<Image>)> = Vec::new();
+    for texture in model.batches.iter().filter_map(|batch| batch.texture.as_ref()) {
+        if cached_textures.contains(texture) || loaded_textures.iter().any(|(name, _)| name == texture) {
+            continue;
+        }
+        loaded_textures.push((texture.clone(), textures::load_blp_image(&texture_path(texture), texture_settings)));
+    }
+
+    Some((model, loaded_textures))
+}
+
+fn texture_path(filename: &str) -> PathBuf {
+    PathBuf::from(format!("./test_data/{}", filename.replace('\\', "/")))
+}
+
+/// Loaded M2 models and their textures, keyed by client path.
+/// Models and textures that failed to load are kept as `None` so they aren't retried for every placement.
+#[derive(Default)]
+pub struct ModelCache {
+    models: HashMap<String, Option<Vec<ModelBatch>>>,
+    /// Models queued on the task pool, so each is only read once.
+    loading: HashSet<String>,
+    textures: HashMap<String, Option<Handle<Image>>>,
+    bounds: HashMap<String, Option<(Vec3, Vec3)>>,
+}
+
+impl ModelCache {
+    /// A model's bounding box from its header, in the model's own (Z up) coordinates.
+    pub fn bounds(&mut self, filename: &str) -> Option<(Vec3, Vec3)> {
+        *self.bounds.entry(filename.to_string())
+            .or_insert_with(|| M2Model::bounding_box_from_file(&model_path(filename)))
+    }
+
+    /// A model's parts, loading it on the spot if it isn't cached yet.
+    pub fn model(
+        &mut self,
+        filename: &str,
+        meshes: &mut Assets<Mesh>,
+        materials: &mut Assets<StandardMaterial>,
+        images: &mut ResMut<Assets<Image>>,
+        texture_settings: &TextureSettings,
+    ) -> Option<ModelParts> {
+        if !self.models.contains_key(filename) {
+            let cached_textures: HashSet<String> = self.textures.keys().cloned().collect();
+            let loaded = load_model(filename, &cached_textures, texture_settings);
+            self.insert_model(filename, loaded, meshes, materials, images);
+        }
+
+        self.models.get(filename)
+            .cloned()
+            .flatten()
+            .map(|batches| batches.into_iter().map(|batch| (batch.mesh, batch.material)).collect())
+    }
+
+    /// A model's batches if it's been loaded, with the inner `None` for models that failed to load.
+    /// Otherwise the model is queued on the task pool, to be picked up by `load_models`, and this returns `None`.
+    pub fn model_or_queue(&mut self, filename: &str, commands: &mut Commands, texture_settings: &TextureSettings) -> Option<Option<Vec<ModelBatch>>> {
+        if let Some(batches) = self.models.get(filename) {
+            return Some(batches.clone())
+        }
+
+        if self.loading.insert(filename.to_string()) {
+            let task_filename = filename.to_string();
+            let cached_textures: HashSet<String> = self.textures.keys().cloned().collect();
+            let texture_settings = texture_settings.clone();
+            let task = AsyncComputeTaskPool::get().spawn(async move {
+                load_model(&task_filename, &cached_textures, &texture_settings)
+            });
+
+            commands.spawn().insert(ModelLoadingTask(filename.to_string(), task));
+        }
+
+        None
+    }
+
+    /// Turn a model read by `load_model` into meshes and materials, shared by every placement of it.
+    fn insert_model(
+        &mut self,
+        filename: &str,
+        loaded: Option<LoadedModel>,
+        meshes: &mut Assets<Mesh>,
+        materials: &mut Assets<StandardMaterial>,
+        images: &mut ResMut<Assets<Image>>,
+    ) {
+        self.loading.remove(filename);
+        if self.models.contains_key(filename) {
+            return
+        }
+
+        let batches = loaded.map(|(model, loaded_textures)| {
+            for (texture, image) in loaded_textures {
+                self.textures.entry(texture).or_insert_with(|| image.map(|image| images.add(image)));
+            }
+
+            model.batches.iter()
+                .map(|batch| {
+                    let texture = batch.texture.as_ref()
+                        .and_then(|texture| self.textures.get(texture).cloned().flatten());
+
+                    let material = StandardMaterial {
+                        base_color: if texture.is_some() { Color::WHITE } else { Color::GRAY },
+                        base_color_texture: texture.clone(),
+                        alpha_mode: match batch.blend {
+                            M2Blend::Opaque => AlphaMode::Opaque,
+                            M2Blend::AlphaKey => AlphaMode::Mask(0.5),
+                            M2Blend::Blend => AlphaMode::Blend,
+                        },
+                        perceptual_roughness: 0.9,
+                        reflectance: 0.1,
+                        // Foliage is made of single-sided cards, and the axis swap flips the winding anyway.
+                        double_sided: true,
+                        cull_mode: None,
+                        ..default()
+                    };
+
+                    ModelBatch {
+                        mesh: meshes.add(model.batch_mesh(batch)),
+                        material: materials.add(material),
+                        texture,
+                        blend: batch.blend,
+                    }
+                })
+                .collect()
+        });
+
+        self.models.insert(filename.to_string(), batches);
+    }
+
+    /// A texture shared by models and WMOs, or `None` if it couldn't be loaded and they should go untextured.
+    pub fn texture(&mut self, filename: &str, images: &mut ResMut<Assets<Image>>, texture_settings: &TextureSettings) -> Option<Handle<Image>> {
+        self.textures.entry(filename.to_string())
+            .or_insert_with(|| textures::load_blp_image(&texture_path(filename), texture_settings).map(|texture| images.add(texture)))
+            .clone()
+    }
+}
+
+/// Move models that have finished loading on the task pool into the cache.
+pub fn load_models(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut images: ResMut<Assets<Image>>,
+    mut model_cache: ResMut<ModelCache>,
+    mut model_tasks: Query<(Entity, &mut ModelLoadingTask)>,
+) {
+    for (entity, mut task) in &mut model_tasks {
+        if let Some(loaded) = future::block_on(future::poll_once(&mut task.1)) {
+            model_cache.insert_model(&task.0, loaded, &mut meshes, &mut materials, &mut images);
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+/// Which loaded tiles have had their placements spawned, and which tile spawned each placement, by its MDDF/MODF unique ID.
+/// Objects crossing tile borders are listed by every tile they touch, so only the first tile to get to one spawns it.
+/// When that tile is unloaded, another loaded tile listing the object takes over.
+#[derive(Default)]
+pub struct SpawnedPlacements {
+    tiles: HashSet<ADTPosition>,
+    owners: HashMap<u32, ADTPosition>,
+}
+
+impl SpawnedPlacements {
+    /// Forget tiles that have been unloaded, along with the placements they spawned.
+    pub fn forget_unloaded(&mut self, adt_entities_lookup: &HashMap<ADTPosition, Vec<Entity>>) {
+        let tiles = self.tiles.len();
+        self.tiles.retain(|position| adt_entities_lookup.contains_key(position));
+        self.owners.retain(|_, position| adt_entities_lookup.contains_key(position));
+
+        // Placements the unloaded tiles shared with loaded ones need spawning again, so visit every tile once more.
+        if self.tiles.len() != tiles {
+            self.tiles.clear();
+        }
+    }
+
+    pub fn is_done(&self, tile: &ADTPosition) -> bool {
+        self.tiles.contains(tile)
+    }
+
+    pub fn is_spawned(&self, unique_id: u32) -> bool {
+        self.owners.contains_key(&unique_id)
+    }
+
+    pub fn spawn(&mut self, unique_id: u32, tile: &ADTPosition) {
+        self.owners.insert(unique_id, tile.clone());
+    }
+
+    /// Mark every placement listed by a tile as handled, so it isn't visited again.
+    pub fn done(&mut self, tile: &ADTPosition) {
+        self.tiles.insert(tile.clone());
+    }
+}
+
+/// Path to an M2 in the extracted data. Older placements still refer to `.mdx` models, which were renamed.
+pub fn model_path(filename: &str) -> PathBuf {
+    let filename = filename.replace('\\', "/");
+    let filename = match filename.rsplit_once('.') {
+        Some((stem, "mdx" | "mdl" | "MDX" | "MDL")) => format!("{}.m2", stem),
+        _ => filename,
+    };
+
+    PathBuf::from(format!("./test_data/{}", filename))
+}
+
+/// Spawn the doodads of each tile once its terrain has been rendered.
+/// Models are loaded on the task pool, and a tile is revisited each frame until all of its models are in.
+/// Each visit spawns an instanced entity per batch of every model it found ready, holding all of that model's placements.
+/// The entities are added to the entity list of the tile that spawned them, so they're despawned along with it.
+pub fn render_doodads(
+    mut commands: Commands,
+    mut model_cache: ResMut<ModelCache>,
+    extras_lookup: Res<HashMap<ADTPosition, AdtExtras>>,
+    mut adt_entities_lookup: ResMut<HashMap<ADTPosition, Vec<Entity>>>,
+    settings: Res<ObjectSettings>,
+    texture_settings: Res<TextureSettings>,
+    mut spawned: Local<SpawnedPlacements>,
+) {
+    // Forget tiles that have been unloaded, so they're populated again if they come back into range.
+    spawned.forget_unloaded(&adt_entities_lookup);
+    if !settings.models {
+        return
+    }
+
+    for (position, entities) in adt_entities_lookup.iter_mut() {
+        if spawned.is_done(position) {
+            continue;
+        }
+        let extras = match extras_lookup.get(position) {
+            Some(extras) => extras,
+            None => continue,
+        };
+
+        let mut waiting = false;
+        let mut instances: HashMap<&str, (Vec<ModelBatch>, Vec<Mat4>)> = HashMap::new();
+        for placement in &extras.doodads {
+            if spawned.is_spawned(placement.unique_id) {
+                continue;
+            }
+
+            let batches = match model_cache.model_or_queue(&placement.filename, &mut commands, &texture_settings) {
+                Some(batches) => batches,
+                None => {
+                    waiting = true;
+                    continue;
+                }
+            };
+            // Models that failed to load count as spawned, so they aren't looked at again.
+            spawned.spawn(placement.unique_id, position);
+
+            if let Some(batches) = batches {
+                let transform = coordinates::placement_transform(placement.position, placement.rotation, placement.scale);
+                instances.entry(placement.filename.as_str())
+                    .or_insert_with(|| (batches, Vec::new()))
+                    .1
+                    .push(transform.compute_matrix());
+            }
+        }
+
+        for (batches, transforms) in instances.into_values() {
+            for batch in batches {
+                let entity = commands
+                    .spawn_bundle(SpatialBundle {
+                        visibility: Visibility { is_visible: settings.shows(true, false, false) },
+                        ..default()
+                    })
+                    .insert_bundle((
+                        batch.mesh,
+                        DoodadInstances { transforms: transforms.clone(), texture: batch.texture, blend: batch.blend },
+                        // The entity sits at the origin, so its mesh's bounds say nothing about where the placements are.
+                        NoFrustumCulling,
+                        Doodad,
+                    ))
+                    .id();
+                entities.push(entity);
+            }
+        }
+
+        if !waiting {
+            spawned.done(position);
+        }
+    }
+}
+
+impl ObjectSettings {
+    /// Whether an object should be shown, from the markers on its entity. Doodads inside WMOs need both enabled.
+    pub fn shows(&self, doodad: bool, wmo: bool, placeholder: bool) -> bool {
+        let mode = if placeholder { self.bounding_boxes } else { self.models };
+        mode && (!doodad || self.doodads) && (!wmo || self.wmos)
+    }
+}
+
+/// Show or hide objects as they're toggled.
+pub fn apply_object_settings(
+    settings: Res<ObjectSettings>,
+    mut objects: Query<(&mut Visibility, Option<&Doodad>, Option<&Wmo>, Option<&Placeholder>), Or<(With<Doodad>, With<Wmo>)>>,
+) {
+    if !settings.is_changed() {
+        return
+    }
+
+    for (mut visibility, doodad, wmo, placeholder) in &mut objects {
+        visibility.is_visible = settings.shows(doodad.is_some(), wmo.is_some(), placeholder.is_some());
+    }
+}
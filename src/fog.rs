@@ -0,0 +1,114 @@
+use bevy::{prelude::*, reflect::TypeUuid};
+
+use crate::coordinates;
+use crate::materials::{CustomMaterial, HorizonMaterial, WaterMaterial};
+
+/// The `forge::fog` shader module, imported by every fogged material's shader.
+const FOG_SHADER_HANDLE: HandleUntyped = HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 0x5f0a_8c2e_91d4_b736);
+
+/// Registers the shared fog shader module, which has to exist before any shader importing it is compiled.
+pub struct FogShaderPlugin;
+
+impl Plugin for FogShaderPlugin {
+    fn build(&self, app: &mut App) {
+        let mut shaders = app.world.resource_mut::<Assets<Shader>>();
+        shaders.set_untracked(FOG_SHADER_HANDLE, Shader::from_wgsl(include_str!("../assets/shaders/fog.wgsl")));
+    }
+}
+
+/// How quickly fog thickens between its start and end distances.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FogMode {
+    Off,
+    Linear,
+    Exponential,
+}
+
+impl FogMode {
+    pub const OPTIONS: [FogMode; 3] = [FogMode::Off, FogMode::Linear, FogMode::Exponential];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            FogMode::Off => "Off",
+            FogMode::Linear => "Linear",
+            FogMode::Exponential => "Exponential",
+        }
+    }
+
+    /// Value of the mode in the shaders' `fog_params.x`.
+    fn shader_value(&self) -> f32 {
+        match self {
+            FogMode::Off => 0.0,
+            FogMode::Linear => 1.0,
+            FogMode::Exponential => 2.0,
+        }
+    }
+}
+
+/// Distance fog for the terrain, liquids and horizon.
+/// Start and end are fractions of the load radius, so the fog follows `CHUNK_RENDER_DISTANCE`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FogSettings {
+    pub mode: FogMode,
+    pub color: Color,
+    pub start: f32,
+    pub end: f32,
+}
+
+impl Default for FogSettings {
+    fn default() -> Self {
+        Self {
+            mode: FogMode::Off,
+            color: Color::rgb(0.55, 0.66, 0.78),
+            start: 0.4,
+            end: 1.0,
+        }
+    }
+}
+
+impl FogSettings {
+    pub fn enabled(&self) -> bool {
+        self.mode != FogMode::Off
+    }
+
+    /// Distance from the camera that's always loaded, in yards.
+    /// Tiles are loaded in a square around the camera's tile, so this is to the nearest edge of that square.
+    pub fn load_radius() -> f32 {
+        (crate::CHUNK_RENDER_DISTANCE / 2) as f32 * coordinates::ADT_SIZE
+    }
+
+    /// Mode, start and end distance packed for the shaders.
+    pub fn params(&self) -> Vec4 {
+        let radius = Self::load_radius();
+        Vec4::new(self.mode.shader_value(), self.start * radius, self.end.max(self.start + 0.01) * radius, 0.0)
+    }
+}
+
+/// Push changes to `FogSettings` into every terrain, liquid and horizon material.
+/// The clear colour follows the fog, so anything past the fog's end blends into the background.
+pub fn apply_fog(
+    settings: Res<FogSettings>,
+    mut clear_color: ResMut<ClearColor>,
+    mut terrain_materials: ResMut<Assets<CustomMaterial>>,
+    mut water_materials: ResMut<Assets<WaterMaterial>>,
+    mut horizon_materials: ResMut<Assets<HorizonMaterial>>,
+) {
+    if !settings.is_changed() {
+        return
+    }
+
+    clear_color.0 = if settings.enabled() { settings.color } else { ClearColor::default().0 };
+
+    for (_, material) in terrain_materials.iter_mut() {
+        material.fog_color = settings.color;
+        material.fog_params = settings.params();
+    }
+    for (_, material) in water_materials.iter_mut() {
+        material.fog_color = settings.color;
+        material.fog_params = settings.params();
+    }
+    for (_, material) in horizon_materials.iter_mut() {
+        material.fog_color = settings.color;
+        material.fog_params = settings.params();
+    }
+}
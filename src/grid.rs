@@ -0,0 +1,204 @@
+//! Debug overlay of ADT borders, chunk borders and the 8x8 cell grid, with tile and chunk indices labelled in world space.
+
+use bevy::{prelude::*, utils::hashbrown::{HashMap, HashSet}};
+use bevy_egui::{egui, EguiContext};
+use bevy_flycam::FlyCam;
+
+use wow_chunky::files;
+
+use crate::coordinates::{self, ADTPosition};
+use crate::lines;
+use crate::terrain::TerrainQuery;
+
+/// Lines float this far above the terrain, so they aren't hidden inside it.
+const GRID_LIFT: f32 = 0.3;
+/// Chunk labels further than this from the camera are left out, to keep the screen readable.
+const CHUNK_LABEL_DISTANCE: f32 = 200.0;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GridSettings {
+    pub adt_borders: bool,
+    pub chunk_borders: bool,
+    pub cells: bool,
+    pub labels: bool,
+}
+
+impl GridSettings {
+    fn draws_lines(&self) -> bool {
+        self.adt_borders || self.chunk_borders || self.cells
+    }
+}
+
+/// Marker for the overlay's line meshes.
+#[derive(Component)]
+pub struct GridOverlay;
+
+/// Whether a world coordinate lies on a border between tiles.
+fn on_adt_border(value: f32) -> bool {
+    let tiles = (coordinates::PLACEMENT_OFFSET - value) / coordinates::ADT_SIZE;
+    (tiles - tiles.round()).abs() < 0.001
+}
+
+/// Grid lines over the loaded terrain, following each chunk's outer vertices: ADT borders, chunk borders and cell lines.
+/// Borders shared by two chunks are drawn by both, on top of each other.
+fn grid_segments(adts: &HashMap<ADTPosition, Option<files::ADT>>) -> [Vec<(Vec3, Vec3)>; 3] {
+    let mut adt_borders = Vec::new();
+    let mut chunk_borders = Vec::new();
+    let mut cells = Vec::new();
+
+    for adt in adts.values().flatten() {
+        for chunk in adt.mcnk.iter() {
+            let outer = |row: usize, column: usize| chunk.mcvt.heights.get(row * 17 + column).map(|v| Vec3::new(v.x, v.y, v.z));
+
+            for i in 0..9 {
+                let lines: [Vec<Vec3>; 2] = [
+                    (0..9).filter_map(|j| outer(i, j)).collect(),
+                    (0..9).filter_map(|j| outer(j, i)).collect(),
+                ];
+
+                for line in lines {
+                    let (first, last) = match (line.first(), line.last()) {
+                        (Some(first), Some(last)) => (*first, *last),
+                        _ => continue,
+                    };
+
+                    // A line's constant coordinate is whichever of X and Y doesn't change along it.
+                    let constant = if (last.x - first.x).abs() < (last.y - first.y).abs() { first.x } else { first.y };
+                    let target = if i != 0 && i != 8 {
+                        &mut cells
+                    } else if on_adt_border(constant) {
+                        &mut adt_borders
+                    } else {
+                        &mut chunk_borders
+                    };
+
+                    // Swap into Bevy's axes, like the terrain mesh.
+                    target.extend(line.windows(2).map(|pair| (
+                        Vec3::new(pair[0].x, pair[0].z + GRID_LIFT, pair[0].y),
+                        Vec3::new(pair[1].x, pair[1].z + GRID_LIFT, pair[1].y),
+                    )));
+                }
+            }
+        }
+    }
+
+    [adt_borders, chunk_borders, cells]
+}
+
+/// Rebuild the overlay whenever the set of rendered tiles or the settings change.
+#[allow(clippy::too_many_arguments)]
+pub fn render_grid(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    settings: Res<GridSettings>,
+    adts: Res<HashMap<ADTPosition, Option<files::ADT>>>,
+    adt_entities_lookup: Res<HashMap<ADTPosition, Vec<Entity>>>,
+    overlay: Query<Entity, With<GridOverlay>>,
+    mut drawn: Local<Option<(GridSettings, HashSet<ADTPosition>)>>,
+) {
+    let rendered: HashSet<ADTPosition> = adt_entities_lookup.keys().cloned().collect();
+    let wanted = if settings.draws_lines() { Some((settings.clone(), rendered)) } else { None };
+    if *drawn == wanted {
+        return
+    }
+
+    for entity in &overlay {
+        commands.entity(entity).despawn();
+    }
+    *drawn = wanted;
+    if drawn.is_none() {
+        return
+    }
+
+    let [adt_borders, mut chunk_borders, cells] = grid_segments(&adts);
+    // Tile edges are chunk borders too, so they're drawn as such while ADT borders are hidden.
+    if !settings.adt_borders {
+        chunk_borders.extend_from_slice(&adt_borders);
+    }
+
+    let layers = [
+        (settings.adt_borders, adt_borders, Color::rgb(1.0, 0.2, 0.2)),
+        (settings.chunk_borders, chunk_borders, Color::rgb(1.0, 0.9, 0.3)),
+        (settings.cells, cells, Color::rgb(0.6, 0.6, 0.6)),
+    ];
+
+    for (enabled, segments, color) in layers {
+        if !enabled || segments.is_empty() {
+            continue;
+        }
+
+        commands
+            .spawn_bundle(PbrBundle {
+                mesh: meshes.add(lines::line_mesh(&segments)),
+                material: materials.add(lines::line_material(color)),
+                ..default()
+            })
+            .insert(GridOverlay);
+    }
+}
+
+/// Label each loaded tile with its ADT indices, and nearby chunks with their MCNK indices.
+pub fn grid_labels(
+    mut egui_context: ResMut<EguiContext>,
+    settings: Res<GridSettings>,
+    windows: Res<Windows>,
+    camera: Query<(&Camera, &GlobalTransform), With<FlyCam>>,
+    adts: Res<HashMap<ADTPosition, Option<files::ADT>>>,
+    terrain: TerrainQuery,
+) {
+    if !settings.labels {
+        return
+    }
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    let (camera, camera_transform) = camera.single();
+    let camera_position = camera_transform.translation();
+
+    let mut labels: Vec<(Vec3, String, egui::Color32)> = Vec::new();
+    for (tile, adt) in adts.iter() {
+        let adt = match adt {
+            Some(adt) => adt,
+            None => continue,
+        };
+
+        // Tile rows run along X, like `ADTPosition::from`.
+        let centre_x = coordinates::PLACEMENT_OFFSET - (tile.y as f32 + 0.5) * coordinates::ADT_SIZE;
+        let centre_z = coordinates::PLACEMENT_OFFSET - (tile.x as f32 + 0.5) * coordinates::ADT_SIZE;
+        let centre = Vec3::new(centre_x, 0.0, centre_z);
+        if let Some(height) = terrain.height(centre) {
+            labels.push((
+                Vec3::new(centre_x, height + 10.0, centre_z),
+                format!("ADT {}_{}", tile.x, tile.y),
+                egui::Color32::from_rgb(255, 90, 90),
+            ));
+        }
+
+        for chunk in adt.mcnk.iter() {
+            // The middle outer vertex is the centre of the chunk.
+            let centre = match chunk.mcvt.heights.get(4 * 17 + 4) {
+                Some(v) => Vec3::new(v.x, v.z + 2.0, v.y),
+                None => continue,
+            };
+            if centre.distance(camera_position) < CHUNK_LABEL_DISTANCE {
+                labels.push((centre, format!("{}, {}", chunk.x, chunk.y), egui::Color32::from_rgb(255, 230, 80)));
+            }
+        }
+    }
+
+    let painter = egui_context.ctx_mut().layer_painter(egui::LayerId::new(egui::Order::Background, egui::Id::new("grid_labels")));
+    for (position, text, color) in labels {
+        // Viewport coordinates start at the bottom left, egui's at the top left.
+        if let Some(screen) = camera.world_to_viewport(camera_transform, position) {
+            painter.text(
+                egui::pos2(screen.x, window.height() - screen.y),
+                egui::Align2::CENTER_CENTER,
+                text,
+                egui::FontId::proportional(14.0),
+                color,
+            );
+        }
+    }
+}
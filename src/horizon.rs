@@ -0,0 +1,144 @@
+//! Low resolution terrain from the map's WDL, drawn where the full ADTs aren't loaded.
+
+use std::path::Path;
+
+use bevy::{prelude::*, utils::hashbrown::HashMap};
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+
+use wow_chunky::files;
+
+use crate::coordinates::{self, ADTPosition};
+use crate::materials::HorizonMaterial;
+use crate::raw;
+
+/// MARE stores a 17x17 grid of outer heights per tile, followed by 16x16 inner heights we don't use.
+const WDL_VERTICES: usize = 17;
+const MAP_TILES: usize = 64;
+
+/// Marker for a tile's horizon mesh.
+#[derive(Component)]
+pub struct Horizon(pub ADTPosition);
+
+/// Outer heights for each tile with an MARE entry, in rows along WoW's X axis like MCVT.
+fn read_wdl(path: &Path) -> Option<Vec<(ADTPosition, Vec<f32>)>> {
+    let data = std::fs::read(path).ok()?;
+    let (_, maof) = raw::chunks(&data).find(|(magic, _)| magic == b"MAOF")?;
+
+    let mut tiles = Vec::new();
+    for y in 0..MAP_TILES {
+        for x in 0..MAP_TILES {
+            // Offsets point at the MARE chunk's header, from the start of the file. Zero means no tile.
+            let offset = raw::read_u32(maof, (y * MAP_TILES + x) * 4)? as usize;
+            if offset == 0 {
+                continue;
+            }
+
+            let mare = match data.get(offset..).and_then(|data| raw::chunks(data).next()) {
+                Some((magic, mare)) if &magic == b"MARE" => mare,
+                _ => continue,
+            };
+
+            let heights: Option<Vec<f32>> = (0..WDL_VERTICES * WDL_VERTICES)
+                .map(|i| raw::read_u16(mare, i * 2).map(|h| h as i16 as f32))
+                .collect();
+
+            if let Some(heights) = heights {
+                tiles.push((ADTPosition { x: x as u32, y: y as u32 }, heights));
+            }
+        }
+    }
+
+    Some(tiles)
+}
+
+/// Build a tile's horizon mesh in world space, using the same axes as `create_ground_mesh`.
+fn create_horizon_mesh(position: &ADTPosition, heights: &[f32]) -> Mesh {
+    let step = coordinates::ADT_SIZE / (WDL_VERTICES - 1) as f32;
+    let height = |row: usize, col: usize| heights[row.min(WDL_VERTICES - 1) * WDL_VERTICES + col.min(WDL_VERTICES - 1)];
+
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    for row in 0..WDL_VERTICES {
+        for col in 0..WDL_VERTICES {
+            let wow_x = coordinates::PLACEMENT_OFFSET - position.y as f32 * coordinates::ADT_SIZE - row as f32 * step;
+            let wow_y = coordinates::PLACEMENT_OFFSET - position.x as f32 * coordinates::ADT_SIZE - col as f32 * step;
+            positions.push([wow_x, height(row, col), wow_y]);
+            uvs.push([col as f32 / (WDL_VERTICES - 1) as f32, row as f32 / (WDL_VERTICES - 1) as f32]);
+
+            // Central differences, falling back to one-sided ones at the tile's edges.
+            let (row_before, row_after) = (row.saturating_sub(1), row + 1);
+            let (col_before, col_after) = (col.saturating_sub(1), col + 1);
+            let d_row = (height(row_after, col) - height(row_before, col)) / ((row_after.min(WDL_VERTICES - 1) - row_before) as f32 * step);
+            let d_col = (height(row, col_after) - height(row, col_before)) / ((col_after.min(WDL_VERTICES - 1) - col_before) as f32 * step);
+            // Rows and columns run towards -X and -Z in Bevy, hence the signs.
+            normals.push(Vec3::new(d_row, 1.0, d_col).normalize().to_array());
+        }
+    }
+
+    let mut indices: Vec<u32> = Vec::new();
+    for row in 0..WDL_VERTICES - 1 {
+        for col in 0..WDL_VERTICES - 1 {
+            let current_index = (row * WDL_VERTICES + col) as u32;
+            let next_row = current_index + WDL_VERTICES as u32;
+
+            indices.extend([current_index + 1, next_row, current_index]);
+            indices.extend([next_row + 1, next_row, current_index + 1]);
+        }
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+
+    mesh
+}
+
+/// Spawn a horizon mesh for every tile in the map's WDL.
+pub fn setup_horizon(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<HorizonMaterial>>,
+    wdt: Res<files::WDT>,
+) {
+    let tiles = match read_wdl(&wdt.path.with_extension("wdl")) {
+        Some(tiles) => tiles,
+        None => return,
+    };
+
+    let material = materials.add(HorizonMaterial {
+        color: Color::rgb(0.34, 0.38, 0.28),
+        // Filled in by `fog::apply_fog`.
+        ..default()
+    });
+
+    for (position, heights) in tiles {
+        commands
+            .spawn_bundle(MaterialMeshBundle {
+                mesh: meshes.add(create_horizon_mesh(&position, &heights)),
+                material: material.clone(),
+                ..default()
+            })
+            .insert(Horizon(position));
+    }
+}
+
+/// Hide the horizon under tiles whose full detail terrain has been rendered.
+pub fn update_horizon(
+    adt_entities_lookup: Res<HashMap<ADTPosition, Vec<Entity>>>,
+    mut horizon: Query<(&Horizon, &mut Visibility)>,
+) {
+    if !adt_entities_lookup.is_changed() {
+        return
+    }
+
+    for (tile, mut visibility) in &mut horizon {
+        let is_visible = !adt_entities_lookup.contains_key(&tile.0);
+        // Only write on change, to keep change detection quiet for the tiles that stay as they are.
+        if visibility.is_visible != is_visible {
+            visibility.is_visible = is_visible;
+        }
+    }
+}
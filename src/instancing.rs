@@ -0,0 +1,341 @@
+//! GPU instancing for ADT doodads, following Bevy's `shader_instancing` example.
+//! Each tile spawns one entity per model batch, holding the transforms of every placement of that model.
+//! The transforms are uploaded as a per-instance vertex buffer, so the batch is drawn once for all of them.
+
+use bevy::{
+    core_pipeline::core_3d::{AlphaMask3d, Opaque3d, Transparent3d},
+    ecs::{query::QueryItem, system::{lifetimeless::{Read, SQuery, SRes}, SystemParamItem}},
+    pbr::{MeshPipeline, MeshPipelineKey, MeshUniform, SetMeshBindGroup, SetMeshViewBindGroup},
+    prelude::*,
+    render::{
+        extract_component::{ExtractComponent, ExtractComponentPlugin},
+        mesh::{GpuBufferInfo, MeshVertexBufferLayout},
+        render_asset::RenderAssets,
+        render_phase::{AddRenderCommand, DrawFunctions, EntityRenderCommand, RenderCommandResult, RenderPhase, SetItemPipeline, TrackedRenderPass},
+        render_resource::*,
+        renderer::RenderDevice,
+        texture::FallbackImage,
+        view::ExtractedView,
+        RenderApp, RenderStage,
+    },
+};
+
+use crate::m2::M2Blend;
+
+/// Every placement of one model batch on a tile, drawn in a single instanced call.
+/// The entity's own transform is left at the identity, the placements are all in `transforms`.
+#[derive(Component, Clone)]
+pub struct DoodadInstances {
+    pub transforms: Vec<Mat4>,
+    pub texture: Option<Handle<Image>>,
+    pub blend: M2Blend,
+}
+
+impl DoodadInstances {
+    /// Average position of the placements, used to sort blended batches.
+    fn center(&self) -> Vec3 {
+        let sum: Vec3 = self.transforms.iter().map(|transform| transform.w_axis.truncate()).sum();
+        sum / self.transforms.len().max(1) as f32
+    }
+}
+
+impl ExtractComponent for DoodadInstances {
+    type Query = &'static DoodadInstances;
+    type Filter = ();
+
+    fn extract_component(item: QueryItem<Self::Query>) -> Self {
+        item.clone()
+    }
+}
+
+pub struct DoodadInstancingPlugin;
+
+impl Plugin for DoodadInstancingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(ExtractComponentPlugin::<DoodadInstances>::default());
+        app.sub_app_mut(RenderApp)
+            .add_render_command::<Opaque3d, DrawDoodad>()
+            .add_render_command::<AlphaMask3d, DrawDoodad>()
+            .add_render_command::<Transparent3d, DrawDoodad>()
+            .init_resource::<DoodadPipeline>()
+            .init_resource::<SpecializedMeshPipelines<DoodadPipeline>>()
+            .add_system_to_stage(RenderStage::Prepare, prepare_doodad_instances)
+            .add_system_to_stage(RenderStage::Queue, queue_doodads);
+    }
+}
+
+/// The placements' transforms on the GPU, one `Mat4` per instance.
+#[derive(Component)]
+struct DoodadInstanceBuffer {
+    buffer: Buffer,
+    length: u32,
+}
+
+/// The batch's texture and sampler, bound at group 2.
+#[derive(Component)]
+struct DoodadTextureBindGroup(BindGroup);
+
+/// Upload the instance transforms and bind the texture of every visible doodad batch.
+/// Batches whose texture hasn't reached the GPU yet are skipped until it has.
+fn prepare_doodad_instances(
+    mut commands: Commands,
+    pipeline: Res<DoodadPipeline>,
+    render_device: Res<RenderDevice>,
+    images: Res<RenderAssets<Image>>,
+    fallback_image: Res<FallbackImage>,
+    doodads: Query<(Entity, &DoodadInstances), With<MeshUniform>>,
+) {
+    for (entity, instances) in &doodads {
+        // Untextured batches still need something bound, the shader just doesn't sample it.
+        let (texture_view, sampler) = match &instances.texture {
+            Some(texture) => match images.get(texture) {
+                Some(image) => (&image.texture_view, &image.sampler),
+                None => continue,
+            },
+            None => (&fallback_image.texture_view, &fallback_image.sampler),
+        };
+
+        let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+            label: Some("doodad_texture_bind_group"),
+            layout: &pipeline.texture_layout,
+            entries: &[
+                BindGroupEntry { binding: 0, resource: BindingResource::TextureView(texture_view) },
+                BindGroupEntry { binding: 1, resource: BindingResource::Sampler(sampler) },
+            ],
+        });
+
+        let contents: Vec<u8> = instances.transforms.iter()
+            .flat_map(|transform| transform.to_cols_array())
+            .flat_map(f32::to_le_bytes)
+            .collect();
+        let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            label: Some("doodad_instance_buffer"),
+            contents: &contents,
+            usage: BufferUsages::VERTEX,
+        });
+
+        commands.entity(entity).insert_bundle((
+            DoodadInstanceBuffer { buffer, length: instances.transforms.len() as u32 },
+            DoodadTextureBindGroup(bind_group),
+        ));
+    }
+}
+
+/// Add each doodad batch to the phase matching its blend mode, like `MaterialPlugin` does for `AlphaMode`.
+#[allow(clippy::too_many_arguments)]
+fn queue_doodads(
+    opaque_draw_functions: Res<DrawFunctions<Opaque3d>>,
+    alpha_mask_draw_functions: Res<DrawFunctions<AlphaMask3d>>,
+    transparent_draw_functions: Res<DrawFunctions<Transparent3d>>,
+    doodad_pipeline: Res<DoodadPipeline>,
+    msaa: Res<Msaa>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<DoodadPipeline>>,
+    mut pipeline_cache: ResMut<PipelineCache>,
+    meshes: Res<RenderAssets<Mesh>>,
+    doodads: Query<(Entity, &Handle<Mesh>, &DoodadInstances), (With<MeshUniform>, With<DoodadTextureBindGroup>)>,
+    mut views: Query<(&ExtractedView, &mut RenderPhase<Opaque3d>, &mut RenderPhase<AlphaMask3d>, &mut RenderPhase<Transparent3d>)>,
+) {
+    let draw_opaque = opaque_draw_functions.read().get_id::<DrawDoodad>().unwrap();
+    let draw_alpha_mask = alpha_mask_draw_functions.read().get_id::<DrawDoodad>().unwrap();
+    let draw_transparent = transparent_draw_functions.read().get_id::<DrawDoodad>().unwrap();
+    let msaa_key = MeshPipelineKey::from_msaa_samples(msaa.samples);
+
+    for (view, mut opaque_phase, mut alpha_mask_phase, mut transparent_phase) in &mut views {
+        let view_row_2 = view.transform.compute_matrix().inverse().row(2);
+
+        for (entity, mesh_handle, instances) in &doodads {
+            let mesh = match meshes.get(mesh_handle) {
+                Some(mesh) => mesh,
+                None => continue,
+            };
+
+            let mut mesh_key = msaa_key | MeshPipelineKey::from_primitive_topology(mesh.primitive_topology);
+            if instances.blend == M2Blend::Blend {
+                mesh_key |= MeshPipelineKey::TRANSPARENT_MAIN_PASS;
+            }
+            let key = DoodadPipelineKey {
+                mesh: mesh_key,
+                blend: instances.blend,
+                textured: instances.texture.is_some(),
+            };
+
+            let pipeline = match pipelines.specialize(&mut pipeline_cache, &doodad_pipeline, key, &mesh.layout) {
+                Ok(pipeline) => pipeline,
+                Err(err) => {
+                    error!("{}", err);
+                    continue;
+                }
+            };
+
+            // Distance along the view's Z axis, as the phases sort on.
+            let distance = view_row_2.dot(instances.center().extend(1.0));
+            match instances.blend {
+                M2Blend::Opaque => opaque_phase.add(Opaque3d { distance, pipeline, entity, draw_function: draw_opaque }),
+                M2Blend::AlphaKey => alpha_mask_phase.add(AlphaMask3d { distance, pipeline, entity, draw_function: draw_alpha_mask }),
+                M2Blend::Blend => transparent_phase.add(Transparent3d { distance, pipeline, entity, draw_function: draw_transparent }),
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DoodadPipelineKey {
+    mesh: MeshPipelineKey,
+    blend: M2Blend,
+    textured: bool,
+}
+
+/// Bevy's mesh pipeline with the instance buffer added, and the batch's texture at group 2.
+pub struct DoodadPipeline {
+    shader: Handle<Shader>,
+    mesh_pipeline: MeshPipeline,
+    texture_layout: BindGroupLayout,
+}
+
+impl FromWorld for DoodadPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let texture_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("doodad_texture_layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        Self {
+            shader: world.resource::<AssetServer>().load("shaders/doodad.wgsl"),
+            mesh_pipeline: world.resource::<MeshPipeline>().clone(),
+            texture_layout,
+        }
+    }
+}
+
+impl SpecializedMeshPipeline for DoodadPipeline {
+    type Key = DoodadPipelineKey;
+
+    fn specialize(&self, key: Self::Key, layout: &MeshVertexBufferLayout) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+        let mut descriptor = self.mesh_pipeline.specialize(key.mesh, layout)?;
+
+        let mut shader_defs = Vec::new();
+        if key.textured {
+            shader_defs.push("TEXTURED".to_string());
+        }
+        match key.blend {
+            M2Blend::Opaque => {}
+            M2Blend::AlphaKey => shader_defs.push("ALPHA_MASK".to_string()),
+            M2Blend::Blend => shader_defs.push("ALPHA_BLEND".to_string()),
+        }
+
+        descriptor.vertex.shader = self.shader.clone();
+        descriptor.vertex.shader_defs.extend(shader_defs.iter().cloned());
+        // A column of the placement's transform per location, after the mesh's position, normal and UV.
+        // M2 meshes have no tangents or vertex colours, so nothing else uses locations 3 and up.
+        descriptor.vertex.buffers.push(VertexBufferLayout {
+            array_stride: std::mem::size_of::<Mat4>() as u64,
+            step_mode: VertexStepMode::Instance,
+            attributes: (0..4u32)
+                .map(|column| VertexAttribute {
+                    format: VertexFormat::Float32x4,
+                    offset: column as u64 * VertexFormat::Float32x4.size(),
+                    shader_location: 3 + column,
+                })
+                .collect(),
+        });
+
+        if let Some(fragment) = descriptor.fragment.as_mut() {
+            fragment.shader = self.shader.clone();
+            fragment.shader_defs.extend(shader_defs);
+        }
+
+        descriptor.layout = Some(vec![
+            self.mesh_pipeline.view_layout.clone(),
+            self.mesh_pipeline.mesh_layout.clone(),
+            self.texture_layout.clone(),
+        ]);
+        // Foliage is made of single-sided cards, and the axis swap flips the winding anyway.
+        descriptor.primitive.cull_mode = None;
+
+        Ok(descriptor)
+    }
+}
+
+type DrawDoodad = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    SetMeshBindGroup<1>,
+    SetDoodadTextureBindGroup<2>,
+    DrawDoodadInstances,
+);
+
+struct SetDoodadTextureBindGroup<const I: usize>;
+
+impl<const I: usize> EntityRenderCommand for SetDoodadTextureBindGroup<I> {
+    type Param = SQuery<Read<DoodadTextureBindGroup>>;
+
+    fn render<'w>(
+        _view: Entity,
+        item: Entity,
+        bind_groups: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        match bind_groups.get_inner(item) {
+            Ok(bind_group) => {
+                pass.set_bind_group(I, &bind_group.0, &[]);
+                RenderCommandResult::Success
+            }
+            Err(_) => RenderCommandResult::Failure,
+        }
+    }
+}
+
+/// Draw the batch's mesh once per placement, with the instance buffer in vertex slot 1.
+struct DrawDoodadInstances;
+
+impl EntityRenderCommand for DrawDoodadInstances {
+    type Param = (SRes<RenderAssets<Mesh>>, SQuery<Read<Handle<Mesh>>>, SQuery<Read<DoodadInstanceBuffer>>);
+
+    fn render<'w>(
+        _view: Entity,
+        item: Entity,
+        (meshes, mesh_query, instance_buffers): SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let gpu_mesh = match mesh_query.get(item).ok().and_then(|handle| meshes.into_inner().get(handle)) {
+            Some(gpu_mesh) => gpu_mesh,
+            None => return RenderCommandResult::Failure,
+        };
+        let instances = match instance_buffers.get_inner(item) {
+            Ok(instances) => instances,
+            Err(_) => return RenderCommandResult::Failure,
+        };
+
+        pass.set_vertex_buffer(0, gpu_mesh.vertex_buffer.slice(..));
+        pass.set_vertex_buffer(1, instances.buffer.slice(..));
+        match &gpu_mesh.buffer_info {
+            GpuBufferInfo::Indexed { buffer, index_format, count } => {
+                pass.set_index_buffer(buffer.slice(..), 0, *index_format);
+                pass.draw_indexed(0..*count, 0, 0..instances.length);
+            }
+            GpuBufferInfo::NonIndexed { vertex_count } => {
+                pass.draw(0..*vertex_count, 0..instances.length);
+            }
+        }
+
+        RenderCommandResult::Success
+    }
+}
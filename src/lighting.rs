@@ -0,0 +1,204 @@
+use bevy::{prelude::*, utils::hashbrown::HashMap};
+
+use wow_chunky::files;
+
+use crate::coordinates;
+use crate::dbc::{self, DbcTable, LightBand, LightRecord, MapRecord, LIGHT_BAND_DAY};
+
+/// Sun and ambient settings used to light the terrain.
+/// Angles are in degrees, with an azimuth of 0 pointing the sun down Bevy's -Z axis.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TerrainLighting {
+    pub sun_azimuth: f32,
+    pub sun_elevation: f32,
+    pub sun_color: Color,
+    pub sun_illuminance: f32,
+    pub ambient_color: Color,
+    pub ambient_brightness: f32,
+}
+
+impl Default for TerrainLighting {
+    fn default() -> Self {
+        Self {
+            sun_azimuth: 135.0,
+            sun_elevation: 50.0,
+            sun_color: Color::rgb(1.0, 0.96, 0.88),
+            sun_illuminance: 12_000.0,
+            ambient_color: Color::rgb(0.62, 0.68, 0.8),
+            ambient_brightness: 0.35,
+        }
+    }
+}
+
+impl TerrainLighting {
+    /// Rotation for a directional light so it shines from the configured sun position.
+    pub fn sun_rotation(&self) -> Quat {
+        Quat::from_euler(
+            EulerRot::YXZ,
+            self.sun_azimuth.to_radians(),
+            -self.sun_elevation.to_radians(),
+            0.0,
+        )
+    }
+}
+
+/// Marker for the directional light acting as the sun.
+#[derive(Component)]
+pub struct Sun;
+
+pub fn setup_sun(
+    mut commands: Commands,
+    lighting: Res<TerrainLighting>,
+) {
+    commands
+        .spawn_bundle(DirectionalLightBundle {
+            directional_light: DirectionalLight {
+                color: lighting.sun_color,
+                illuminance: lighting.sun_illuminance,
+                shadows_enabled: false,
+                ..default()
+            },
+            transform: Transform::from_rotation(lighting.sun_rotation()),
+            ..default()
+        })
+        .insert(Sun);
+}
+
+/// Push changes to `TerrainLighting` into the sun and ambient light.
+pub fn apply_lighting(
+    lighting: Res<TerrainLighting>,
+    mut sun: Query<(&mut DirectionalLight, &mut Transform), With<Sun>>,
+    mut ambient: ResMut<AmbientLight>,
+) {
+    if !lighting.is_changed() {
+        return
+    }
+
+    for (mut light, mut transform) in &mut sun {
+        light.color = lighting.sun_color;
+        light.illuminance = lighting.sun_illuminance;
+        transform.rotation = lighting.sun_rotation();
+    }
+
+    ambient.color = lighting.ambient_color;
+    ambient.brightness = lighting.ambient_brightness;
+}
+
+/// Rows of LightIntBand per LightParams entry, one for each colour the client keys over the day.
+const BANDS_PER_PARAMS: u32 = 18;
+const BAND_DIFFUSE: u32 = 0;
+const BAND_AMBIENT: u32 = 1;
+const BAND_SKY_TOP: u32 = 2;
+const BAND_HORIZON: u32 = 6;
+const BAND_FOG: u32 = 7;
+const BAND_SUN: u32 = 9;
+
+/// The client's lights for the current map, from Light.dbc and the colour bands of their clear weather params.
+/// Empty if the tables aren't in the test data, in which case lighting is left to the time of day.
+pub struct LightTable {
+    lights: Vec<LightRecord>,
+    bands: HashMap<u32, LightBand<Color>>,
+}
+
+/// Colours from the light tables at a place and time of day.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightSample {
+    pub diffuse: Color,
+    pub ambient: Color,
+    pub sky_top: Color,
+    pub horizon: Color,
+    pub fog: Color,
+    pub sun: Color,
+}
+
+/// Blend between two colours, with `t` clamped to the range between them.
+pub fn mix_color(a: Color, b: Color, t: f32) -> Color {
+    Color::from(Vec4::from(a).lerp(Vec4::from(b), t.clamp(0.0, 1.0)))
+}
+
+impl LightSample {
+    fn mix(&self, other: &LightSample, t: f32) -> LightSample {
+        LightSample {
+            diffuse: mix_color(self.diffuse, other.diffuse, t),
+            ambient: mix_color(self.ambient, other.ambient, t),
+            sky_top: mix_color(self.sky_top, other.sky_top, t),
+            horizon: mix_color(self.horizon, other.horizon, t),
+            fog: mix_color(self.fog, other.fog, t),
+            sun: mix_color(self.sun, other.sun, t),
+        }
+    }
+}
+
+impl LightTable {
+    /// Colours of one light at a time in half minutes.
+    fn colors(&self, light: &LightRecord, time: u32) -> Option<LightSample> {
+        let band = |index: u32| {
+            let id = light.params[0] * BANDS_PER_PARAMS - (BANDS_PER_PARAMS - 1) + index;
+            self.bands.get(&id)?.sample(time, mix_color)
+        };
+
+        Some(LightSample {
+            diffuse: band(BAND_DIFFUSE)?,
+            ambient: band(BAND_AMBIENT)?,
+            sky_top: band(BAND_SKY_TOP)?,
+            horizon: band(BAND_HORIZON)?,
+            fog: band(BAND_FOG)?,
+            sun: band(BAND_SUN)?,
+        })
+    }
+
+    /// How strongly a light applies at a position: fully inside its falloff start, fading out by its end.
+    fn weight(light: &LightRecord, position: Vec3) -> f32 {
+        let distance = position.distance(coordinates::placement_to_bevy(light.position));
+        if distance <= light.falloff_start {
+            1.0
+        } else if distance >= light.falloff_end {
+            0.0
+        } else {
+            1.0 - (distance - light.falloff_start) / (light.falloff_end - light.falloff_start)
+        }
+    }
+
+    /// Blend the lights reaching a position in Bevy space at an hour of the day.
+    /// Local lights are layered over the map's global light, nearest last so it wins where they overlap.
+    pub fn sample(&self, position: Vec3, hour: f32) -> Option<LightSample> {
+        let time = (hour / 24.0 * LIGHT_BAND_DAY as f32) as u32;
+
+        let mut local: Vec<(f32, LightSample)> = self.lights.iter()
+            .filter(|light| light.params[0] != 0 && !light.is_global())
+            .filter_map(|light| {
+                let weight = Self::weight(light, position);
+                if weight <= 0.0 {
+                    return None
+                }
+                self.colors(light, time).map(|colors| (weight, colors))
+            })
+            .collect();
+        local.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let global = self.lights.iter()
+            .find(|light| light.params[0] != 0 && light.is_global())
+            .and_then(|light| self.colors(light, time));
+
+        local.into_iter().fold(global, |blended, (weight, colors)| match blended {
+            Some(blended) => Some(blended.mix(&colors, weight)),
+            None => Some(colors),
+        })
+    }
+}
+
+/// Load the lights for the map whose directory matches the WDT's name.
+pub fn setup_light_table(
+    mut commands: Commands,
+    wdt: Res<files::WDT>,
+    maps: Res<DbcTable<MapRecord>>,
+    lights: Res<DbcTable<LightRecord>>,
+) {
+    let lights: Vec<LightRecord> = match dbc::current_map(&maps, &wdt) {
+        Some(map) => lights.iter().filter(|light| light.map_id == map.id).cloned().collect(),
+        None => Vec::new(),
+    };
+    let bands = if lights.is_empty() { HashMap::new() } else { dbc::read_int_bands() };
+
+    commands.insert_resource(LightTable { lights, bands });
+}
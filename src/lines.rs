@@ -0,0 +1,58 @@
+//! Line list meshes for debug overlays.
+
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+
+/// Wireframe of a cube from -0.5 to 0.5 on each axis, to be scaled into place by its transform.
+pub fn unit_box_mesh() -> Mesh {
+    let corners: Vec<[f32; 3]> = (0..8)
+        .map(|i| [
+            if i & 1 == 0 { -0.5 } else { 0.5 },
+            if i & 2 == 0 { -0.5 } else { 0.5 },
+            if i & 4 == 0 { -0.5 } else { 0.5 },
+        ])
+        .collect();
+
+    // Each edge joins two corners that differ in a single axis bit.
+    let mut edges = Vec::new();
+    for i in 0..8_u32 {
+        for axis in [1, 2, 4] {
+            if i & axis == 0 {
+                edges.extend([i, i | axis]);
+            }
+        }
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::LineList);
+    mesh.set_indices(Some(Indices::U32(edges)));
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, corners);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0.0, 1.0, 0.0]; 8]);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, vec![[0.0, 0.0]; 8]);
+
+    mesh
+}
+
+/// Separate line segments, in whatever space the entity's transform places them.
+pub fn line_mesh(segments: &[(Vec3, Vec3)]) -> Mesh {
+    let positions: Vec<[f32; 3]> = segments.iter()
+        .flat_map(|(start, end)| [start.to_array(), end.to_array()])
+        .collect();
+    let count = positions.len();
+
+    let mut mesh = Mesh::new(PrimitiveTopology::LineList);
+    mesh.set_indices(Some(Indices::U32((0..count as u32).collect())));
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0.0, 1.0, 0.0]; count]);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, vec![[0.0, 0.0]; count]);
+
+    mesh
+}
+
+/// Flat coloured material for lines, unaffected by lighting.
+pub fn line_material(color: Color) -> StandardMaterial {
+    StandardMaterial {
+        base_color: color,
+        unlit: true,
+        ..default()
+    }
+}
@@ -0,0 +1,320 @@
+//! Liquid surfaces, on the same 9x9 vertex grid as a chunk's outer terrain vertices.
+
+use std::path::PathBuf;
+
+use bevy::{prelude::*, utils::hashbrown::HashMap};
+
+use crate::dbc::{DbcTable, LiquidTypeRecord};
+use crate::materials::WaterMaterial;
+use crate::raw;
+use crate::textures::{self, TextureSettings};
+
+/// Vertices and tiles along each side of a chunk's liquid grid.
+pub const LIQUID_VERTICES: usize = 9;
+pub const LIQUID_TILES: usize = 8;
+
+/// Size of one MCLQ liquid instance, including its flow data.
+const MCLQ_INSTANCE_SIZE: usize = 0x324;
+const MCLQ_VERTICES_OFFSET: usize = 0x08;
+const MCLQ_VERTEX_SIZE: usize = 8;
+const MCLQ_TILES_OFFSET: usize = 0x290;
+/// Tile flag marking a tile as having no liquid to render.
+const MCLQ_TILE_HIDDEN: u8 = 0x08;
+
+/// MH2O per-chunk header and liquid instance sizes.
+const MH2O_HEADER_SIZE: usize = 12;
+const MH2O_INSTANCE_SIZE: usize = 24;
+/// Liquid vertex format values at or above this are LiquidObject IDs rather than formats.
+const MH2O_FIRST_LIQUID_OBJECT: u16 = 42;
+
+/// Liquid textures are numbered animation frames, played back at this rate.
+const LIQUID_FRAMES_PER_SECOND: f32 = 30.0;
+const LIQUID_MAX_FRAMES: usize = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LiquidKind {
+    River,
+    Ocean,
+    Magma,
+    Slime,
+}
+
+impl LiquidKind {
+    /// MCNK flag bits for each liquid kind, in the order their MCLQ instances are stored.
+    pub const MCNK_FLAGS: [(u32, LiquidKind); 4] = [
+        (0x04, LiquidKind::River),
+        (0x08, LiquidKind::Ocean),
+        (0x10, LiquidKind::Magma),
+        (0x20, LiquidKind::Slime),
+    ];
+
+    pub const ALL: [LiquidKind; 4] = [LiquidKind::River, LiquidKind::Ocean, LiquidKind::Magma, LiquidKind::Slime];
+
+    /// Kind from LiquidType.dbc's sound bank, which groups every liquid type into one of the four basic kinds.
+    pub fn from_sound_bank(sound_bank: u32) -> Option<Self> {
+        LiquidKind::ALL.get(sound_bank as usize).copied()
+    }
+
+    /// Best guess at a kind from a LiquidType ID, for when LiquidType.dbc isn't available.
+    pub fn from_liquid_type(id: u16) -> Self {
+        match id {
+            // Water, ocean, magma and slime, then their slow and fast variants.
+            1..=12 => LiquidKind::ALL[((id - 1) % 4) as usize],
+            14 => LiquidKind::Ocean,
+            15 | 19 => LiquidKind::Magma,
+            20 | 21 => LiquidKind::Slime,
+            _ => LiquidKind::River,
+        }
+    }
+
+    /// Colour in the shallows, with the alpha channel as opacity.
+    pub fn color(&self) -> Color {
+        match self {
+            LiquidKind::River => Color::rgba(0.2, 0.2, 0.6, 0.65),
+            LiquidKind::Ocean => Color::rgba(0.08, 0.16, 0.42, 0.8),
+            LiquidKind::Magma => Color::rgba(0.85, 0.28, 0.05, 1.0),
+            LiquidKind::Slime => Color::rgba(0.25, 0.55, 0.1, 0.9),
+        }
+    }
+
+    /// Colour and opacity of deep liquid.
+    pub fn deep_color(&self) -> Color {
+        match self {
+            LiquidKind::River => Color::rgba(0.06, 0.1, 0.32, 0.88),
+            LiquidKind::Ocean => Color::rgba(0.02, 0.05, 0.2, 0.96),
+            _ => self.color(),
+        }
+    }
+
+    /// Depths at which the shoreline has faded in, and at which the deep colour is reached.
+    pub fn fade_depths(&self) -> Vec2 {
+        match self {
+            LiquidKind::River => Vec2::new(0.6, 6.0),
+            LiquidKind::Ocean => Vec2::new(1.0, 25.0),
+            _ => Vec2::new(0.3, 2.0),
+        }
+    }
+
+    pub fn emissive(&self) -> Color {
+        match self {
+            LiquidKind::Magma => Color::rgb(0.7, 0.22, 0.02),
+            LiquidKind::Slime => Color::rgb(0.06, 0.16, 0.02),
+            _ => Color::BLACK,
+        }
+    }
+
+    /// Client path of a liquid animation frame, numbered from 1.
+    fn frame_path(&self, frame: usize) -> String {
+        match self {
+            LiquidKind::River => format!("XTextures/river/lake_a.{}.blp", frame),
+            LiquidKind::Ocean => format!("XTextures/ocean/ocean_h.{}.blp", frame),
+            LiquidKind::Magma => format!("XTextures/lava/lava.{}.blp", frame),
+            LiquidKind::Slime => format!("XTextures/slime/slime.{}.blp", frame),
+        }
+    }
+}
+
+/// Shared material and animation frames for each kind of liquid.
+pub struct LiquidMaterials {
+    materials: HashMap<LiquidKind, Handle<WaterMaterial>>,
+    frames: HashMap<LiquidKind, Vec<Handle<Image>>>,
+}
+
+impl LiquidMaterials {
+    pub fn get(&self, kind: LiquidKind) -> Handle<WaterMaterial> {
+        self.materials[&kind].clone()
+    }
+}
+
+pub fn setup_liquid_materials(
+    mut commands: Commands,
+    mut water_materials: ResMut<Assets<WaterMaterial>>,
+    mut textures: ResMut<Assets<Image>>,
+    texture_settings: Res<TextureSettings>,
+) {
+    let mut materials = HashMap::new();
+    let mut frames = HashMap::new();
+
+    for kind in LiquidKind::ALL {
+        // Load animation frames until one is missing. Without any, the shader falls back to procedural ripples.
+        let kind_frames: Vec<Handle<Image>> = (1..=LIQUID_MAX_FRAMES)
+            .map(|frame| PathBuf::from(format!("./test_data/{}", kind.frame_path(frame))))
+            .take_while(|path| path.exists())
+            .map_while(|path| textures::load_blp_image(&path, &texture_settings))
+            .map(|texture| textures.add(texture))
+            .collect();
+
+        let material = water_materials.add(WaterMaterial {
+            color: kind.color(),
+            deep_color: kind.deep_color(),
+            fade_depths: kind.fade_depths(),
+            emissive: kind.emissive(),
+            time: 0.0,
+            has_texture: if kind_frames.is_empty() { 0.0 } else { 1.0 },
+            texture: kind_frames.first().cloned(),
+            // Filled in by `fog::apply_fog`.
+            ..default()
+        });
+
+        materials.insert(kind, material);
+        frames.insert(kind, kind_frames);
+    }
+
+    commands.insert_resource(LiquidMaterials { materials, frames });
+}
+
+/// Advance liquid animations, flipping through texture frames where they exist.
+pub fn animate_liquids(
+    time: Res<Time>,
+    liquid_materials: Res<LiquidMaterials>,
+    mut water_materials: ResMut<Assets<WaterMaterial>>,
+) {
+    let seconds = time.seconds_since_startup() as f32;
+
+    for (kind, handle) in liquid_materials.materials.iter() {
+        if let Some(material) = water_materials.get_mut(handle) {
+            material.time = seconds;
+
+            let frames = &liquid_materials.frames[kind];
+            if !frames.is_empty() {
+                let frame = (seconds * LIQUID_FRAMES_PER_SECOND) as usize % frames.len();
+                material.texture = Some(frames[frame].clone());
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ChunkLiquid {
+    pub kind: LiquidKind,
+    /// LiquidType ID, for MH2O liquids.
+    pub liquid_type: Option<u16>,
+    /// Absolute heights of the 9x9 vertices, row by row.
+    pub heights: Vec<f32>,
+    /// Whether each of the 8x8 tiles has liquid to render, row by row.
+    pub tiles: Vec<bool>,
+}
+
+impl ChunkLiquid {
+    /// A flat liquid plane covering the whole chunk.
+    pub fn flat(kind: LiquidKind, height: f32) -> Self {
+        Self {
+            kind,
+            liquid_type: None,
+            heights: vec![height; LIQUID_VERTICES * LIQUID_VERTICES],
+            tiles: vec![true; LIQUID_TILES * LIQUID_TILES],
+        }
+    }
+
+    /// Read the legacy MCLQ liquid of an MCNK, which stores one instance per liquid flag set on the chunk.
+    pub fn from_mclq(data: &[u8], mcnk_flags: u32) -> Vec<Self> {
+        LiquidKind::MCNK_FLAGS.iter()
+            .filter(|(flag, _)| mcnk_flags & flag != 0)
+            .enumerate()
+            .map_while(|(i, (_, kind))| {
+                let instance = data.get(i * MCLQ_INSTANCE_SIZE..(i + 1) * MCLQ_INSTANCE_SIZE)?;
+                Self::from_mclq_instance(instance, *kind)
+            })
+            .collect()
+    }
+
+    fn from_mclq_instance(data: &[u8], kind: LiquidKind) -> Option<Self> {
+        // Water and magma vertices differ in their first four bytes, but both end with the height.
+        let heights = (0..LIQUID_VERTICES * LIQUID_VERTICES)
+            .map(|i| raw::read_f32(data, MCLQ_VERTICES_OFFSET + i * MCLQ_VERTEX_SIZE + 4))
+            .collect::<Option<Vec<f32>>>()?;
+
+        let tiles = data.get(MCLQ_TILES_OFFSET..MCLQ_TILES_OFFSET + LIQUID_TILES * LIQUID_TILES)?
+            .iter()
+            .map(|tile| tile & MCLQ_TILE_HIDDEN == 0)
+            .collect();
+
+        Some(Self { kind, liquid_type: None, heights, tiles })
+    }
+
+    /// Read the liquid instances for one chunk from a tile's MH2O (WotLK+) chunk.
+    pub fn from_mh2o(data: &[u8], chunk_index: usize) -> Vec<Self> {
+        let header = chunk_index * MH2O_HEADER_SIZE;
+        let (instances_offset, layer_count) = match (raw::read_u32(data, header), raw::read_u32(data, header + 4)) {
+            (Some(offset), Some(count)) if offset != 0 => (offset as usize, count as usize),
+            _ => return Vec::new(),
+        };
+
+        (0..layer_count)
+            .filter_map(|i| Self::from_mh2o_instance(data, instances_offset + i * MH2O_INSTANCE_SIZE))
+            .collect()
+    }
+
+    fn from_mh2o_instance(data: &[u8], offset: usize) -> Option<Self> {
+        let liquid_type = raw::read_u16(data, offset)?;
+        let vertex_format = raw::read_u16(data, offset + 0x02)?;
+        let min_height = raw::read_f32(data, offset + 0x04)?;
+        let x_offset = raw::read_u8(data, offset + 0x0C)? as usize;
+        let y_offset = raw::read_u8(data, offset + 0x0D)? as usize;
+        let width = raw::read_u8(data, offset + 0x0E)? as usize;
+        let height = raw::read_u8(data, offset + 0x0F)? as usize;
+        let exists_offset = raw::read_u32(data, offset + 0x10)? as usize;
+        let vertex_offset = raw::read_u32(data, offset + 0x14)? as usize;
+
+        if x_offset + width > LIQUID_TILES || y_offset + height > LIQUID_TILES {
+            return None
+        }
+
+        let kind = LiquidKind::from_liquid_type(liquid_type);
+
+        // Later clients give a LiquidObject ID in place of the vertex format, where magma and slime carry UVs instead of depths.
+        let vertex_format = if vertex_format >= MH2O_FIRST_LIQUID_OBJECT {
+            match kind {
+                LiquidKind::Magma | LiquidKind::Slime => 1,
+                _ => 0,
+            }
+        } else {
+            vertex_format
+        };
+
+        // Formats 0, 1 and 3 start with a height per vertex, while format 2 (depth only) is flat.
+        let mut heights = vec![min_height; LIQUID_VERTICES * LIQUID_VERTICES];
+        if vertex_offset != 0 && vertex_format != 2 {
+            for row in 0..=height {
+                for column in 0..=width {
+                    let index = row * (width + 1) + column;
+                    let vertex = (y_offset + row) * LIQUID_VERTICES + x_offset + column;
+                    heights[vertex] = raw::read_f32(data, vertex_offset + index * 4)?;
+                }
+            }
+        }
+
+        // Tiles inside the instance exist unless its bitmap says otherwise.
+        let mut tiles = vec![false; LIQUID_TILES * LIQUID_TILES];
+        for row in 0..height {
+            for column in 0..width {
+                let bit = row * width + column;
+                let exists = exists_offset == 0
+                    || raw::read_u8(data, exists_offset + bit / 8)? & (1 << (bit % 8)) != 0;
+
+                tiles[(y_offset + row) * LIQUID_TILES + x_offset + column] = exists;
+            }
+        }
+
+        Some(Self { kind, liquid_type: Some(liquid_type), heights, tiles })
+    }
+
+    /// Replace the kind guessed from the LiquidType ID with the one LiquidType.dbc gives, if it's available.
+    pub fn refine_kind(&mut self, liquid_types: &DbcTable<LiquidTypeRecord>) {
+        let kind = self.liquid_type
+            .and_then(|id| liquid_types.get(id as u32))
+            .and_then(|liquid_type| LiquidKind::from_sound_bank(liquid_type.sound_bank));
+
+        if let Some(kind) = kind {
+            self.kind = kind;
+        }
+    }
+
+    pub fn height(&self, row: usize, column: usize) -> f32 {
+        self.heights[row * LIQUID_VERTICES + column]
+    }
+
+    pub fn renders_tile(&self, row: usize, column: usize) -> bool {
+        self.tiles[row * LIQUID_TILES + column]
+    }
+}
@@ -0,0 +1,249 @@
+//! Just enough of the M2 model format to draw a model's static geometry.
+
+use std::path::{Path, PathBuf};
+
+use bevy::{prelude::*, utils::hashbrown::HashMap};
+
+use crate::raw;
+
+/// First version with external `.skin` files (WotLK). Earlier models store their skin profiles inline.
+const VERSION_EXTERNAL_SKINS: u32 = 264;
+/// First version with the larger, 48 byte skin sections (TBC).
+const VERSION_LARGE_SKIN_SECTIONS: u32 = 260;
+
+const VERTEX_SIZE: usize = 48;
+const TEXTURE_SIZE: usize = 16;
+const BATCH_SIZE: usize = 24;
+
+/// Header offsets that moved when skins were split out into their own files.
+struct HeaderLayout {
+    vertices: usize,
+    skin_profiles: usize,
+    textures: usize,
+    materials: usize,
+    texture_combos: usize,
+    bounding_box: usize,
+}
+
+const LEGACY_LAYOUT: HeaderLayout = HeaderLayout {
+    vertices: 0x44,
+    skin_profiles: 0x4C,
+    textures: 0x5C,
+    materials: 0x84,
+    texture_combos: 0x94,
+    bounding_box: 0xB4,
+};
+
+const LAYOUT: HeaderLayout = HeaderLayout {
+    vertices: 0x3C,
+    skin_profiles: 0x44,
+    textures: 0x50,
+    materials: 0x70,
+    texture_combos: 0x80,
+    bounding_box: 0xA0,
+};
+
+/// How a batch's texture should be blended, from M2Material.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum M2Blend {
+    Opaque,
+    AlphaKey,
+    Blend,
+}
+
+/// A part of the model drawn with a single texture.
+#[derive(Debug, Clone)]
+pub struct M2Batch {
+    /// Triangle list indexing into the model's vertices.
+    pub indices: Vec<u32>,
+    /// Client path of the texture, when it's hardcoded rather than chosen at runtime.
+    pub texture: Option<String>,
+    pub blend: M2Blend,
+}
+
+/// Static M2 geometry, in the model's own coordinates (Z up).
+#[derive(Debug, Clone)]
+pub struct M2Model {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<[f32; 2]>,
+    pub batches: Vec<M2Batch>,
+}
+
+/// Read an (offset, count) M2Array as a slice of `size` byte elements.
+fn m2_array(data: &[u8], offset: usize, size: usize) -> Option<&[u8]> {
+    let count = raw::read_u32(data, offset)? as usize;
+    let start = raw::read_u32(data, offset + 4)? as usize;
+    data.get(start..start + count * size)
+}
+
+impl M2Model {
+    /// Read the header's bounding box, without loading any geometry.
+    /// Returns the minimum and maximum corners, in the model's own coordinates.
+    pub fn bounding_box_from_file(path: &Path) -> Option<(Vec3, Vec3)> {
+        let file = std::fs::read(path).ok()?;
+        let data = md20(&file)?;
+        let layout = header_layout(data)?;
+
+        Some((raw::read_vec3(data, layout.bounding_box)?, raw::read_vec3(data, layout.bounding_box + 12)?))
+    }
+
+    pub fn from_file(path: &Path) -> Option<Self> {
+        let file = std::fs::read(path).ok()?;
+        let data = md20(&file)?;
+        let version = raw::read_u32(data, 0x04)?;
+        let layout = header_layout(data)?;
+
+        let vertices = m2_array(data, layout.vertices, VERTEX_SIZE)?;
+        let mut positions = Vec::new();
+        let mut normals = Vec::new();
+        let mut uvs = Vec::new();
+        for vertex in vertices.chunks_exact(VERTEX_SIZE) {
+            positions.push(raw::read_vec3(vertex, 0)?);
+            normals.push(raw::read_vec3(vertex, 20)?);
+            uvs.push([raw::read_f32(vertex, 32)?, raw::read_f32(vertex, 36)?]);
+        }
+
+        // Only the first (highest detail) skin profile is used.
+        let skin_file;
+        let (skin, skin_base) = if version >= VERSION_EXTERNAL_SKINS {
+            skin_file = std::fs::read(skin_path(path)).ok()?;
+            (skin_file.as_slice(), 4)
+        } else {
+            (data, raw::read_u32(data, layout.skin_profiles + 4)? as usize)
+        };
+
+        let skin_vertices: Vec<u16> = m2_array(skin, skin_base, 2)?
+            .chunks_exact(2)
+            .filter_map(|v| raw::read_u16(v, 0))
+            .collect();
+        let skin_indices: Vec<u16> = m2_array(skin, skin_base + 0x08, 2)?
+            .chunks_exact(2)
+            .filter_map(|v| raw::read_u16(v, 0))
+            .collect();
+
+        let section_size = if version >= VERSION_LARGE_SKIN_SECTIONS { 48 } else { 32 };
+        let sections = m2_array(skin, skin_base + 0x18, section_size)?;
+        let batches = m2_array(skin, skin_base + 0x20, BATCH_SIZE)?;
+
+        let textures = m2_array(data, layout.textures, TEXTURE_SIZE).unwrap_or_default();
+        let materials = m2_array(data, layout.materials, 4).unwrap_or_default();
+        let texture_combos = m2_array(data, layout.texture_combos, 2).unwrap_or_default();
+
+        let mut model_batches = Vec::new();
+        for batch in batches.chunks_exact(BATCH_SIZE) {
+            let section_index = raw::read_u16(batch, 0x04)? as usize;
+            let material_index = raw::read_u16(batch, 0x0A)? as usize;
+            let texture_combo = raw::read_u16(batch, 0x10)? as usize;
+
+            let section = sections.get(section_index * section_size..(section_index + 1) * section_size)?;
+            // The section's level extends its index start past what fits in a u16.
+            let level = raw::read_u16(section, 0x02)? as usize;
+            let index_start = raw::read_u16(section, 0x08)? as usize + (level << 16);
+            let index_count = raw::read_u16(section, 0x0A)? as usize;
+
+            // Skip the whole batch if any index is out of range, rather than dropping it and shifting every later triangle.
+            let indices: Option<Vec<u32>> = skin_indices.get(index_start..index_start + index_count)?
+                .iter()
+                .map(|i| skin_vertices.get(*i as usize).map(|v| *v as u32).filter(|v| (*v as usize) < positions.len()))
+                .collect();
+            let indices = match indices {
+                Some(indices) => indices,
+                None => continue,
+            };
+
+            let texture = raw::read_u16(texture_combos, texture_combo * 2)
+                .and_then(|texture_index| texture_filename(data, textures, texture_index as usize));
+
+            let blend = match raw::read_u16(materials, material_index * 4 + 2) {
+                Some(0) | None => M2Blend::Opaque,
+                Some(1) => M2Blend::AlphaKey,
+                Some(_) => M2Blend::Blend,
+            };
+
+            model_batches.push(M2Batch { indices, texture, blend });
+        }
+
+        Some(Self {
+            positions,
+            normals,
+            uvs,
+            batches: model_batches,
+        })
+    }
+
+    /// Build a Bevy mesh for one batch.
+    pub fn batch_mesh(&self, batch: &M2Batch) -> Mesh {
+        batch_mesh(&self.positions, &self.normals, &self.uvs, &batch.indices)
+    }
+}
+
+/// Build a mesh from the vertices a batch uses, converting from Z up to Y up the same way as the terrain.
+/// Shared with WMOs, which store their geometry the same way. Both readers reject batches with indices past `positions`.
+pub fn batch_mesh(positions: &[Vec3], normals: &[Vec3], uvs: &[[f32; 2]], batch_indices: &[u32]) -> Mesh {
+    let mut remap: HashMap<u32, u32> = HashMap::new();
+    let mut mesh_positions = Vec::new();
+    let mut mesh_normals = Vec::new();
+    let mut mesh_uvs = Vec::new();
+
+    let indices: Vec<u32> = batch_indices.iter().map(|i| {
+        *remap.entry(*i).or_insert_with(|| {
+            let v = *i as usize;
+            let normal = normals.get(v).copied().unwrap_or(Vec3::Z);
+            mesh_positions.push([positions[v].x, positions[v].z, positions[v].y]);
+            mesh_normals.push([normal.x, normal.z, normal.y]);
+            mesh_uvs.push(uvs.get(v).copied().unwrap_or_default());
+            mesh_positions.len() as u32 - 1
+        })
+    }).collect();
+
+    let mut mesh = Mesh::new(bevy::render::mesh::PrimitiveTopology::TriangleList);
+    mesh.set_indices(Some(bevy::render::mesh::Indices::U32(indices)));
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, mesh_positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, mesh_normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, mesh_uvs);
+
+    mesh
+}
+
+/// Find the MD20 data, unwrapping the MD21 chunk used by Legion+ models.
+/// Unlike the other chunked formats, M2 magics are stored readable, so the chunk header is read here rather than through `raw::chunks`.
+fn md20(file: &[u8]) -> Option<&[u8]> {
+    match file.get(0..4)? {
+        b"MD20" => Some(file),
+        b"MD21" => {
+            let size = raw::read_u32(file, 4)? as usize;
+            file.get(8..8 + size)
+        }
+        _ => None,
+    }
+}
+
+fn header_layout(data: &[u8]) -> Option<&'static HeaderLayout> {
+    let version = raw::read_u32(data, 0x04)?;
+    Some(if version >= VERSION_EXTERNAL_SKINS { &LAYOUT } else { &LEGACY_LAYOUT })
+}
+
+/// Filename of a hardcoded (type 0) texture. Other types are filled in by the client from DBCs.
+fn texture_filename(data: &[u8], textures: &[u8], index: usize) -> Option<String> {
+    let texture = textures.get(index * TEXTURE_SIZE..(index + 1) * TEXTURE_SIZE)?;
+    if raw::read_u32(texture, 0)? != 0 {
+        return None
+    }
+
+    // The filename array's offset is relative to the start of the model, not the texture entry.
+    let count = raw::read_u32(texture, 8)? as usize;
+    let start = raw::read_u32(texture, 12)? as usize;
+    let name = raw::read_cstring(data.get(start..start + count)?, 0)?;
+    if name.is_empty() {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+/// The first skin file for a model, e.g. `Tree01.m2` -> `Tree0100.skin`.
+fn skin_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+    path.with_file_name(format!("{}00.skin", stem))
+}
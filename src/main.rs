@@ -3,7 +3,8 @@ use std::path::PathBuf;
 use bevy::{
     prelude::*,
     render::{render_resource::{Extent3d, TextureDimension, TextureFormat}, settings::WgpuSettings},
-    utils::hashbrown::HashMap, pbr::wireframe::{WireframePlugin, WireframeConfig}, tasks::{AsyncComputeTaskPool, Task}, time::FixedTimestep
+    utils::hashbrown::HashMap, pbr::wireframe::{WireframePlugin, WireframeConfig}, tasks::{AsyncComputeTaskPool, Task}, time::FixedTimestep,
+    transform::TransformSystem,
 };
 
 use bevy_egui::{egui::{self, Color32, TextureFilter, TextureId, Vec2 as BevyVec2}, EguiContext, EguiPlugin};
@@ -17,14 +18,36 @@ use bevy_flycam::{FlyCam, MovementSettings, NoCameraPlayerPlugin};
 
 use futures_lite::future;
 
-use materials::{CustomMaterial, WaterMaterial};
-use wgpu_types::{AddressMode, FilterMode, Features};
+use materials::{CustomMaterial, HorizonMaterial, SkyMaterial, TerrainSettings, WaterMaterial};
+use liquid::{LiquidKind, LiquidMaterials};
+use wgpu_types::{FilterMode, Features};
 
 use wow_chunky::{chunks, files};
 
 
 mod materials;
 mod coordinates;
+mod adt;
+mod dbc;
+mod doodads;
+mod fog;
+mod grid;
+mod horizon;
+mod instancing;
+mod lighting;
+mod lines;
+mod liquid;
+mod m2;
+mod picking;
+mod placeholders;
+mod raw;
+mod ray;
+mod sky;
+mod terrain;
+mod textures;
+mod walk;
+mod wmo;
+mod zones;
 
 static CHUNK_RENDER_DISTANCE: u32 = 4;
 
@@ -42,9 +65,28 @@ fn main() {
             ..default()
         })
         .insert_resource(Msaa { samples: 4 })
+        .insert_resource(lighting::TerrainLighting::default())
+        .insert_resource(TerrainSettings::default())
+        .insert_resource(textures::TextureSettings::default())
+        .insert_resource(doodads::ObjectSettings::default())
+        .insert_resource(fog::FogSettings::default())
+        .insert_resource(sky::TimeOfDay::default())
+        .insert_resource(zones::ZoneSettings::default())
+        .insert_resource(zones::ZoneExport::default())
+        .insert_resource(walk::WalkSettings::default())
+        .insert_resource(picking::TerrainSelection::default())
+        .insert_resource(grid::GridSettings::default())
+        .insert_resource(doodads::ModelCache::default())
+        .insert_resource(wmo::WmoCache::default())
 
         .insert_resource(wdt)
 
+        .insert_resource(dbc::DbcTable::<dbc::MapRecord>::load())
+        .insert_resource(dbc::DbcTable::<dbc::AreaRecord>::load())
+        .insert_resource(dbc::DbcTable::<dbc::LiquidTypeRecord>::load())
+        .insert_resource(dbc::DbcTable::<dbc::LightRecord>::load())
+
+        .insert_resource(HashMap::<(String, usize), (Handle<Image>, bool)>::new())
         .insert_resource(HashMap::<(String, usize), Handle<Image>>::new())
         .insert_resource(HashMap::<(String, (u32, u32), usize), Handle<Image>>::new())
 
@@ -52,11 +94,16 @@ fn main() {
         .insert_resource(HashMap::<coordinates::ChunkPosition, (String, Option<chunks::adt::MTEX>, chunks::adt::MCNK)>::new())
 
         .insert_resource(HashMap::<coordinates::ADTPosition, Option<files::ADT>>::new())
+        .insert_resource(HashMap::<coordinates::ADTPosition, adt::AdtExtras>::new())
 
         .add_plugins(DefaultPlugins)
 
+        .add_plugin(fog::FogShaderPlugin)
         .add_plugin(MaterialPlugin::<CustomMaterial>::default())
         .add_plugin(MaterialPlugin::<WaterMaterial>::default())
+        .add_plugin(MaterialPlugin::<HorizonMaterial>::default())
+        .add_plugin(MaterialPlugin::<SkyMaterial>::default())
+        .add_plugin(instancing::DoodadInstancingPlugin)
 
         .add_plugin(NoCameraPlayerPlugin)
         .insert_resource(MovementSettings {
@@ -68,11 +115,25 @@ fn main() {
         .add_plugin(WireframePlugin)
 
         .add_startup_system(setup)
+        .add_startup_system(lighting::setup_sun)
+        .add_startup_system(lighting::setup_light_table)
+        .add_startup_system(textures::detect_texture_compression)
+        .add_startup_system(liquid::setup_liquid_materials.after(textures::detect_texture_compression))
+        .add_startup_system(placeholders::setup_placeholders)
+        .add_startup_system(horizon::setup_horizon)
+        .add_startup_system(sky::setup_sky)
 
         .add_system(chunk_queuer)
         .add_system(chunk_loader.after(chunk_queuer))
 
         .add_system(render_terrain.after(chunk_loader))
+        .add_system(doodads::load_models)
+        .add_system(doodads::render_doodads.after(render_terrain).after(doodads::load_models))
+        .add_system(wmo::render_wmos.after(render_terrain))
+        .add_system(placeholders::render_placeholders.after(render_terrain))
+        .add_system(horizon::update_horizon.after(render_terrain))
+        .add_system(zones::render_zone_outlines.after(render_terrain))
+        .add_system(grid::render_grid.after(render_terrain))
 
         .add_system_set(
             SystemSet::new()
@@ -80,60 +141,30 @@ fn main() {
                 .with_system(chunk_coordinates.after(chunk_loader))
         )
 
+        .add_system(sky::advance_time_of_day)
+        .add_system(sky::apply_time_of_day.after(sky::advance_time_of_day).before(lighting::apply_lighting))
+        .add_system(sky::follow_camera)
+        .add_system(lighting::apply_lighting)
+        .add_system(materials::apply_terrain_settings)
+        .add_system(fog::apply_fog)
+        .add_system(materials::animate_terrain_layers)
+        .add_system(liquid::animate_liquids)
+        .add_system(textures::apply_texture_settings)
+        .add_system(doodads::apply_object_settings)
+        .add_system(zones::export_zones)
+
         .add_system(input)
         .add_system(ui)
+        .add_system(settings_ui)
+        .add_system(placeholders::placeholder_hover)
+        .add_system(picking::pick_terrain)
+        .add_system(picking::highlight_selection.after(picking::pick_terrain))
+        .add_system(grid::grid_labels)
 
-        .run();
-}
-
-fn generate_image_from_buffer(width: u32, height: u32, data: &[u8]) -> Image {
-    let mut tex = Image::new(
-        Extent3d {
-            width,
-            height,
-            ..default()
-        },
-        TextureDimension::D2,
-        data.to_owned(),
-        TextureFormat::Rgba8Unorm,
-    );
-
-    // Wrap u and v values, to allow for easier tiling.
-    tex.sampler_descriptor = ImageSampler::Descriptor(SamplerDescriptor {
-        mag_filter: FilterMode::Linear,
-        min_filter: FilterMode::Linear,
-        address_mode_u: AddressMode::Repeat,
-        address_mode_v: AddressMode::Repeat,
-        ..default()
-    });
-
-    tex
-}
-
-fn process_blp(raw_filename: &str, textures: &mut ResMut<Assets<Image>>) -> Handle<Image> {
-    let specular_filename = format!(
-        "./test_data/{}_s.blp",
-        raw_filename.replace('\\', "/").replace(".blp", "")
-    );
-    let normal_filename = format!("./test_data/{}", raw_filename.replace('\\', "/"));
-
-    let specular_path = PathBuf::from(&specular_filename);
-    let normal_path = PathBuf::from(&normal_filename);
-
-    let path = if specular_path.exists() {
-        specular_path
-    } else {
-        normal_path
-    };
-
-    // TODO: Specular textures are being loaded, but probably not being used properly.
-    // In-game textures look noticably less flat, even with constrast turned up. Look into improving the lighting quality or handling speculars work properly?
-    let blp = files::BLP::try_from(path.clone())
-        .unwrap_or_else(|_| panic!("BLPs should be valid: {:?}", &path));
+        // After the fly camera moves, but before the move is propagated to what's drawn.
+        .add_system_to_stage(CoreStage::PostUpdate, walk::walk.before(TransformSystem::TransformPropagate))
 
-    let texture = generate_image_from_buffer(blp.width, blp.height, &blp.mipmaps[0].decompressed);
-
-    textures.add(texture)
+        .run();
 }
 
 fn process_alpha_map(data: &[u8], textures: &mut ResMut<Assets<Image>>) -> Handle<Image> {
@@ -160,16 +191,36 @@ fn process_alpha_map(data: &[u8], textures: &mut ResMut<Assets<Image>>) -> Handl
     textures.add(tex)
 }
 
+/// Everything the terrain material needs to know about one texture layer.
+#[derive(Clone)]
+struct TerrainLayer {
+    texture: Handle<Image>,
+    /// Whether the texture's alpha channel holds specular strength.
+    specular: bool,
+    /// How many chunk units one repeat of the texture covers.
+    scale: f32,
+    /// Scrolling velocity in texture repeats per second.
+    scroll: Vec2,
+    /// `_h` height texture, with its MTXP scale and offset.
+    height: Option<(Handle<Image>, f32, f32)>,
+}
+
 fn render_terrain(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<CustomMaterial>>,
-    mut water_materials: ResMut<Assets<WaterMaterial>>,
+    liquid_materials: Res<LiquidMaterials>,
     mut textures: ResMut<Assets<Image>>,
     adts: Res<HashMap<coordinates::ADTPosition, Option<files::ADT>>>,
+    extras_lookup: Res<HashMap<coordinates::ADTPosition, adt::AdtExtras>>,
     mut alpha_lookup: ResMut<HashMap<(String, (u32, u32), usize), Handle<Image>>>,
-    mut blp_lookup: ResMut<HashMap<(String, usize), Handle<Image>>>,
+    mut blp_lookup: ResMut<HashMap<(String, usize), (Handle<Image>, bool)>>,
+    mut height_lookup: ResMut<HashMap<(String, usize), Handle<Image>>>,
     mut adt_entities_lookup: ResMut<HashMap<coordinates::ADTPosition, Vec<Entity>>>,
+    terrain_settings: Res<TerrainSettings>,
+    texture_settings: Res<textures::TextureSettings>,
+    fog_settings: Res<fog::FogSettings>,
+    liquid_types: Res<dbc::DbcTable<dbc::LiquidTypeRecord>>,
 ) {
     // TODO: Sort ADTs by their distance to the camera, and load in order.
 
@@ -180,18 +231,34 @@ fn render_terrain(
         }
 
         if let Some(adt) = adt {
+            let extras = extras_lookup.get(position).cloned().unwrap_or_default();
+
             // Load all BLPs.
             if let Some(mtex) = &adt.mtex {
                 for (i, filename) in mtex.filenames.iter().enumerate() {
-                    let texture = process_blp(filename, &mut textures);
-                    blp_lookup.insert((adt.filename.clone(), i), texture);
+                    // Layers whose texture can't be loaded are left out of the chunk's material.
+                    if let Some(texture) = textures::process_blp(filename, &mut textures, &texture_settings) {
+                        blp_lookup.insert((adt.filename.clone(), i), texture);
+                    }
+
+                    // Height textures are only used alongside MTXP parameters.
+                    if extras.texture_height(i).is_some() {
+                        if let Some(height) = textures::process_height_blp(filename, &mut textures, &texture_settings) {
+                            height_lookup.insert((adt.filename.clone(), i), height);
+                        }
+                    }
                 }
             }
 
             let mut adt_entities: Vec<Entity> = Vec::new();
             // Render chunks.
-            for chunk in adt.mcnk.iter() {
-                let mut layers: Vec<Option<Handle<Image>>> = vec![None, None, None, None];
+            for (chunk_index, chunk) in adt.mcnk.iter().enumerate() {
+                let mut chunk_extras = extras.chunks.get(chunk_index).cloned().unwrap_or_default();
+                for liquid in chunk_extras.liquids.iter_mut() {
+                    liquid.refine_kind(&liquid_types);
+                }
+
+                let mut layers: Vec<Option<TerrainLayer>> = vec![None, None, None, None];
                 // The first layer never uses alpha.
                 let mut alphas: Vec<Option<Handle<Image>>> = vec![
                     Some(process_alpha_map(&vec![0_u8; 64 * 64], &mut textures)),
@@ -201,8 +268,19 @@ fn render_terrain(
 
                 for (i, texture_layer) in chunk.mcly.layers.iter().enumerate() {
                     let texture_id = texture_layer.texture_id as usize;
+                    let height = height_lookup.get(&(adt.filename.clone(), texture_id))
+                        .zip(extras.texture_height(texture_id))
+                        .map(|(height, (scale, offset))| (height.clone(), scale, offset));
+
                     layers[i] = blp_lookup
-                        .get(&(adt.filename.clone(), texture_id)).cloned();
+                        .get(&(adt.filename.clone(), texture_id))
+                        .map(|(texture, specular)| TerrainLayer {
+                            texture: texture.clone(),
+                            specular: *specular,
+                            scale: extras.texture_scale(texture_id),
+                            scroll: chunk_extras.layer_scroll(i),
+                            height,
+                        });
                 }
 
                 for (i, alpha_layer) in chunk.mcal.layers.iter().enumerate() {
@@ -215,10 +293,13 @@ fn render_terrain(
                     &mut commands,
                     &mut meshes,
                     &mut materials,
-                    &mut water_materials,
+                    &liquid_materials,
                     layers,
                     alphas,
                     chunk,
+                    &chunk_extras,
+                    &terrain_settings,
+                    &fog_settings,
                 );
                 adt_entities.extend(chunk_entities);
             }
@@ -232,21 +313,38 @@ fn create_chunk_meshes(
     commands: &mut Commands,
     meshes: &mut ResMut<Assets<Mesh>>,
     materials: &mut ResMut<Assets<CustomMaterial>>,
-    water_materials: &mut ResMut<Assets<WaterMaterial>>,
-    layers: Vec<Option<Handle<Image>>>,
+    liquid_materials: &LiquidMaterials,
+    layers: Vec<Option<TerrainLayer>>,
     alphas: Vec<Option<Handle<Image>>>,
     chunk: &chunks::adt::MCNK,
+    chunk_extras: &adt::ChunkExtras,
+    terrain_settings: &TerrainSettings,
+    fog_settings: &fog::FogSettings,
 ) -> Vec<Entity> {
     let mut chunk_entities: Vec<Entity> = Vec::new();
 
     // Render the ground mesh.
-    let ground_id = create_ground_mesh(commands, meshes, materials, layers, alphas, chunk);
+    let ground_id = create_ground_mesh(commands, meshes, materials, layers, alphas, chunk, terrain_settings, fog_settings);
     chunk_entities.push(ground_id);
 
     // Render water if it exists in the chunk.
-    if chunk.flags.lq_ocean || chunk.flags.lq_magma || chunk.flags.lq_river {
-        let water_id = create_water_mesh(commands, meshes, water_materials, chunk);
-        chunk_entities.push(water_id);
+    let mut liquids = chunk_extras.liquids.clone();
+    if liquids.is_empty() && (chunk.flags.lq_ocean || chunk.flags.lq_magma || chunk.flags.lq_river) {
+        // Fall back to a flat plane if the per-vertex MCLQ data couldn't be read.
+        let kind = if chunk.flags.lq_magma {
+            LiquidKind::Magma
+        } else if chunk.flags.lq_ocean {
+            LiquidKind::Ocean
+        } else {
+            LiquidKind::River
+        };
+        liquids.push(liquid::ChunkLiquid::flat(kind, chunk.mclq.height.max));
+    }
+
+    for liquid in liquids.iter() {
+        if let Some(water_id) = create_water_mesh(commands, meshes, liquid_materials, chunk, liquid) {
+            chunk_entities.push(water_id);
+        }
     }
 
     chunk_entities
@@ -256,45 +354,25 @@ fn create_ground_mesh(
     commands: &mut Commands,
     meshes: &mut ResMut<Assets<Mesh>>,
     materials: &mut ResMut<Assets<CustomMaterial>>,
-    layers: Vec<Option<Handle<Image>>>,
+    layers: Vec<Option<TerrainLayer>>,
     alphas: Vec<Option<Handle<Image>>>,
     chunk: &chunks::adt::MCNK,
+    terrain_settings: &TerrainSettings,
+    fog_settings: &fog::FogSettings,
 ) -> Entity {
-    let mut indices: Vec<u32> = Vec::new();
-    for x in 0..8 {
-        for y in 0..8 {
-            let current_index = y * 17 + x;
-
-            indices.push(current_index + 1);
-            indices.push(current_index + 9);
-            indices.push(current_index);
-
-            indices.push(current_index + 9);
-            indices.push(current_index + 17);
-            indices.push(current_index);
-
-            indices.push(current_index + 18);
-            indices.push(current_index + 17);
-            indices.push(current_index + 9);
-
-            indices.push(current_index + 18);
-            indices.push(current_index + 9);
-            indices.push(current_index + 1);
-        }
-    }
-
-    let indices = mesh::Indices::U32(indices);
+    let indices = mesh::Indices::U32(terrain::ground_indices());
 
     let mut positions = Vec::new();
     let mut normals = Vec::new();
     let mut uvs = Vec::new();
     for (i, position) in chunk.mcvt.heights.iter().enumerate() {
         let position = [position.x, position.z, position.y];
-        let normal = [
+        // MCNR stores signed bytes where 127 == 1.0, so they need normalising before use.
+        let normal = Vec3::new(
             chunk.mcnr.normals[i].x as f32,
             chunk.mcnr.normals[i].z as f32,
             chunk.mcnr.normals[i].y as f32,
-        ];
+        ).normalize_or_zero().to_array();
 
         positions.push(position);
         normals.push(normal);
@@ -307,17 +385,42 @@ fn create_ground_mesh(
     mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
     mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
 
+    let texture = |i: usize| layers[i].as_ref().map(|layer| layer.texture.clone());
+    let height = |i: usize| layers[i].as_ref().and_then(|layer| layer.height.as_ref()).map(|(height, _, _)| height.clone());
+    let has_height_textures = layers.iter().flatten().all(|layer| layer.height.is_some());
+    // Pack a per-layer value into a Vec4, using `default` for unused layers.
+    let per_layer = |value: &dyn Fn(&TerrainLayer) -> f32, default: f32| {
+        let values: Vec<f32> = layers.iter().map(|layer| layer.as_ref().map_or(default, value)).collect();
+        Vec4::from_slice(&values)
+    };
+
     let heightmesh = commands.spawn_bundle(MaterialMeshBundle {
         mesh: meshes.add(mesh),
         material: materials.add(CustomMaterial {
             base_positions: Vec2::new(chunk.position.x, chunk.position.y),
-            layer_1: layers[0].clone(),
-            layer_2: layers[1].clone(),
+            specular_layers: per_layer(&|layer| if layer.specular { 1.0 } else { 0.0 }, 0.0),
+            specular_strength: terrain_settings.specular_strength(),
+            layer_scales: per_layer(&|layer| layer.scale, 1.0),
+            layer_scroll_x: per_layer(&|layer| layer.scroll.x, 0.0),
+            layer_scroll_y: per_layer(&|layer| layer.scroll.y, 0.0),
+            time: 0.0,
+            height_scales: per_layer(&|layer| layer.height.as_ref().map_or(0.0, |(_, scale, _)| *scale), 0.0),
+            height_offsets: per_layer(&|layer| layer.height.as_ref().map_or(1.0, |(_, _, offset)| *offset), 1.0),
+            height_blend: terrain_settings.height_blend(has_height_textures),
+            has_height_textures,
+            fog_color: fog_settings.color,
+            fog_params: fog_settings.params(),
+            layer_1: texture(0),
+            layer_2: texture(1),
             alpha_2: alphas[0].clone(),
-            layer_3: layers[2].clone(),
+            layer_3: texture(2),
             alpha_3: alphas[1].clone(),
-            layer_4: layers[3].clone(),
+            layer_4: texture(3),
             alpha_4: alphas[2].clone(),
+            height_1: height(0),
+            height_2: height(1),
+            height_3: height(2),
+            height_4: height(3),
         }),
         ..default()
     });
@@ -328,27 +431,38 @@ fn create_ground_mesh(
 fn create_water_mesh(
     commands: &mut Commands,
     meshes: &mut ResMut<Assets<Mesh>>,
-    water_materials: &mut ResMut<Assets<WaterMaterial>>,
+    liquid_materials: &LiquidMaterials,
     chunk: &chunks::adt::MCNK,
-) -> Entity {
+    liquid: &liquid::ChunkLiquid,
+) -> Option<Entity> {
     let spread = coordinates::CHUNK_SIZE / 8.;
 
     let chunk_position = [chunk.position.x, chunk.position.y];
-    let max_water_height = chunk.mclq.height.max;
 
     let mut positions = Vec::new();
     let mut normals = Vec::new();
+    let mut depths = Vec::new();
     for x in 0..9 {
         for y in 0..9 {
-            let position = [chunk_position[0] - ((x as f32) * spread), max_water_height, chunk_position[1] - ((y as f32) * spread)];
+            let position = [chunk_position[0] - ((x as f32) * spread), liquid.height(x, y), chunk_position[1] - ((y as f32) * spread)];
             positions.push(position);
             normals.push([1.0, 1.0, 1.0]);
+
+            // The liquid grid lines up with the terrain's outer vertices, which start every 17 heights in MCVT.
+            // Unlike MCLQ's depth bytes, the height difference is available for every kind of liquid.
+            let terrain_height = chunk.mcvt.heights[x * 17 + y].z;
+            depths.push([liquid.height(x, y) - terrain_height, 0.0]);
         }
     }
 
+    // Only cover the tiles that actually have liquid, so partially filled chunks don't become slabs.
     let mut indices: Vec<u32> = Vec::new();
     for x in 0..8 {
         for y in 0..8 {
+            if !liquid.renders_tile(y as usize, x as usize) {
+                continue;
+            }
+
             indices.push(x + 9 + (y * 9));
             indices.push(x + (y * 9));
             indices.push(x + 1 + (y * 9));
@@ -359,20 +473,25 @@ fn create_water_mesh(
         }
     }
 
+    if indices.is_empty() {
+        return None
+    }
+
     let indices = mesh::Indices::U32(indices);
 
     let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
     mesh.set_indices(Some(indices));
     mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
     mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, depths);
 
     let watermesh = commands.spawn_bundle(MaterialMeshBundle {
         mesh: meshes.add(mesh),
-        material: water_materials.add(WaterMaterial {}),
+        material: liquid_materials.get(liquid.kind),
         ..default()
     });
 
-    watermesh.id()
+    Some(watermesh.id())
 }
 
 fn setup(
@@ -384,6 +503,8 @@ fn setup(
                 .looking_at(Vec3::ZERO, Vec3::Y),
             projection: bevy::render::camera::Projection::Perspective(PerspectiveProjection {
                 fov: std::f32::consts::PI / 5.0,
+                // Far enough for the horizon to reach across the whole map.
+                far: coordinates::ADT_SIZE * 64.0,
                 ..default()
             }),
             ..default()
@@ -392,7 +513,7 @@ fn setup(
 }
 
 #[derive(Component)]
-struct AdtParsingTask(coordinates::ADTPosition, Task<Option<files::ADT>>);
+struct AdtParsingTask(coordinates::ADTPosition, Task<Option<(files::ADT, adt::AdtExtras)>>);
 
 
 /// Spawn chunk loading tasks as the camera moves around.
@@ -401,6 +522,7 @@ fn chunk_queuer(
     camera: Query<&mut Transform, With<FlyCam>>,
     wdt: Res<files::WDT>,
     mut adts: ResMut<HashMap<coordinates::ADTPosition, Option<files::ADT>>>,
+    mut extras_lookup: ResMut<HashMap<coordinates::ADTPosition, adt::AdtExtras>>,
     mut adt_entities_lookup: ResMut<HashMap<coordinates::ADTPosition, Vec<Entity>>>,
     chunk_tasks: Query<(Entity, &mut AdtParsingTask)>,
 ) {
@@ -427,6 +549,7 @@ fn chunk_queuer(
     adt_entities_lookup.retain(|k, v| {
         if !adt_coords.contains(k) {
             adts.remove(k);
+            extras_lookup.remove(k);
             for e in v {
                 commands.entity(*e).despawn();
             }
@@ -438,16 +561,14 @@ fn chunk_queuer(
 
     // Add ADT load futures to the queue. 
     for c in adt_coords {
-        let adt_name = format!("{}_{}_{}.adt", wdt.path.file_stem().and_then(|n| n.to_str()).expect("WDT should have a extension."), c.x, c.y);
-        let adt_path = wdt.path
-            .parent().expect("WDT file should be in a folder with the ADT files.")
-            .join(adt_name);
+        let adt_path = adt::tile_path(&wdt.path, &c);
 
         let mphd_flags = wdt.mphd.as_ref().map(|chunk| chunk.flags.clone())
             .expect("WDT should have a valid MPHD chunk");
 
         let task = pool.spawn(async move {
-            files::ADT::from_file(adt_path, &mphd_flags).ok()
+            files::ADT::from_file(adt_path.clone(), &mphd_flags).ok()
+                .map(|adt| (adt, adt::AdtExtras::from_file(&adt_path)))
         });
 
         commands.spawn().insert(AdtParsingTask(c, task));
@@ -457,10 +578,15 @@ fn chunk_queuer(
 fn chunk_loader(
     mut commands: Commands,
     mut adts: ResMut<HashMap::<coordinates::ADTPosition, Option<files::ADT>>>,
+    mut extras_lookup: ResMut<HashMap<coordinates::ADTPosition, adt::AdtExtras>>,
     mut chunk_tasks: Query<(Entity, &mut AdtParsingTask)>,
 ) {
     for (entity, mut task) in &mut chunk_tasks {
-        if let Some(adt) = future::block_on(future::poll_once(&mut task.1)) {
+        if let Some(result) = future::block_on(future::poll_once(&mut task.1)) {
+            let adt = result.map(|(adt, extras)| {
+                extras_lookup.insert(task.0.clone(), extras);
+                adt
+            });
             adts.insert(task.0.clone(), adt);
 
             commands.entity(entity).remove::<AdtParsingTask>();
@@ -500,30 +626,53 @@ fn input(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn ui(
     mut egui_context: ResMut<EguiContext>,
     query: Query<&mut Transform, With<FlyCam>>,
     chunk_lookup: Res<HashMap<coordinates::ChunkPosition, (String, Option<chunks::adt::MTEX>, chunks::adt::MCNK)>>,
     chunk_tasks: Query<(Entity, &mut AdtParsingTask)>,
-    blp_lookup: Res<HashMap<(String, usize), Handle<Image>>>,
+    blp_lookup: Res<HashMap<(String, usize), (Handle<Image>, bool)>>,
     alpha_lookup: Res<HashMap<(String, (u32, u32), usize), Handle<Image>>>,
+    terrain: terrain::TerrainQuery,
+    wdt: Res<files::WDT>,
+    maps: Res<dbc::DbcTable<dbc::MapRecord>>,
+    areas: Res<dbc::DbcTable<dbc::AreaRecord>>,
+    adts: Res<HashMap<coordinates::ADTPosition, Option<files::ADT>>>,
+    selection: Res<picking::TerrainSelection>,
 ) {
     let cam_pos: Vec3 = query.single().translation;
     let world_pos = coordinates::WorldPosition::from(cam_pos);
     let chunk_pos = coordinates::ChunkPosition::from(&world_pos);
-    let location = chunk_lookup.get(&chunk_pos);
+
+    // Inspect the picked chunk if there is one, otherwise the one under the camera.
+    let picked = selection.pick.as_ref().and_then(|pick| {
+        let adt = adts.get(&pick.tile)?.as_ref()?;
+        Some((&adt.filename, &adt.mtex, adt.mcnk.get(pick.chunk_index)?))
+    });
+    let location = picked.or_else(|| chunk_lookup.get(&chunk_pos).map(|(adt, mtex, chunk)| (adt, mtex, chunk)));
+    let inspected_pos = selection.pick.as_ref().map_or(cam_pos, |pick| pick.position);
+
+    let map_name = dbc::current_map(&maps, &wdt)
+        .map(|map| map.name.clone())
+        .or_else(|| wdt.path.file_stem().and_then(|name| name.to_str()).map(String::from))
+        .unwrap_or_default();
 
     // TODO: Display textures + alpha maps of current on screen.
 
     if let Some(location) = location {
         let (adt, mtex, chunk) = location;
 
-        let textures: Vec<TextureId> = chunk.mcly.layers.iter().map(|l| {
-            let blp_handle = blp_lookup.get(&(adt.clone(), l.texture_id as usize)).unwrap();
-            let bevy_texture_id = egui_context.add_image(
-                blp_handle.clone()
-            );
-            bevy_texture_id
+        let area_name = match terrain.area_id(inspected_pos) {
+            Some(area_id) => areas.full_name(area_id),
+            None => String::from("Unknown area"),
+        };
+        let ground = terrain.sample(inspected_pos);
+
+        // Layers whose BLP couldn't be loaded have no texture, like in `create_ground_mesh`.
+        let textures: Vec<Option<TextureId>> = chunk.mcly.layers.iter().map(|l| {
+            blp_lookup.get(&(adt.clone(), l.texture_id as usize))
+                .map(|(blp_handle, _)| egui_context.add_image(blp_handle.clone()))
         }).collect();
 
         let alpha_maps: Vec<TextureId> = chunk.mcal.layers.iter().enumerate().map(|(i, l)| {
@@ -541,7 +690,27 @@ fn ui(
                     .auto_shrink([false, false])
                     .show(ui, |ui| {
                         ui.colored_label(Color32::LIGHT_YELLOW, format!("Loading {} chunks", chunk_tasks.iter().count()));
+                        ui.heading(&area_name);
+                        ui.label(&map_name);
                         ui.label(format!("Position: {:?}", cam_pos));
+                        if let Some(pick) = &selection.pick {
+                            let hit = coordinates::WorldPosition::from(pick.position);
+                            ui.colored_label(
+                                Color32::LIGHT_BLUE,
+                                format!("Picked: {:.3} {:.3} {:.3} (vertex {})", hit.x, hit.y, hit.z, pick.vertex),
+                            );
+                        }
+                        if let Some(ground) = &ground {
+                            ui.label(format!(
+                                "Ground: {:.2} (slope {:.0}°), liquid: {}",
+                                ground.height,
+                                ground.normal.angle_between(Vec3::Y).to_degrees(),
+                                ground.liquid_level.map_or(String::from("none"), |level| format!("{:.2}", level)),
+                            ));
+                            for layer in &ground.layers {
+                                ui.label(format!("{:>4.0}% {}", layer.alpha * 100.0, layer.filename));
+                            }
+                        }
 
                         ui.label(format!(
                             "Chunk: ({}) ({}, {}) {:#?}",
@@ -555,18 +724,197 @@ fn ui(
         egui::Window::new("Textures + Alphas")
             .anchor(egui::Align2::RIGHT_TOP, BevyVec2::new(0.0, 0.0))
             .show(egui_context.ctx_mut(), |ui| {
-                ui.add(egui::widgets::Image::new(textures[0], [128.0, 128.0]));
+                let show_texture = |ui: &mut egui::Ui, texture: Option<TextureId>| match texture {
+                    Some(texture) => { ui.add(egui::widgets::Image::new(texture, [128.0, 128.0])); }
+                    None => { ui.colored_label(Color32::LIGHT_RED, "Missing BLP"); }
+                };
+
+                for (i, texture) in textures.into_iter().enumerate() {
+                    // The first layer never uses alpha.
+                    let alpha = i.checked_sub(1).and_then(|i| alpha_maps.get(i)).copied();
+
+                    ui.horizontal(|ui| {
+                        show_texture(ui, texture);
+                        if let Some(alpha) = alpha {
+                            ui.add(egui::widgets::Image::new(alpha, [128.0, 128.0]));
+                        }
+                    });
+                }
+            });
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn settings_ui(
+    mut egui_context: ResMut<EguiContext>,
+    mut lighting: ResMut<lighting::TerrainLighting>,
+    mut terrain_settings: ResMut<TerrainSettings>,
+    mut texture_settings: ResMut<textures::TextureSettings>,
+    mut object_settings: ResMut<doodads::ObjectSettings>,
+    mut fog_settings: ResMut<fog::FogSettings>,
+    mut time_of_day: ResMut<sky::TimeOfDay>,
+    mut zone_settings: ResMut<zones::ZoneSettings>,
+    zone_export: Res<zones::ZoneExport>,
+    mut walk_settings: ResMut<walk::WalkSettings>,
+    mut grid_settings: ResMut<grid::GridSettings>,
+) {
+    let mut new_lighting = lighting.clone();
+    let mut new_terrain_settings = terrain_settings.clone();
+    let mut new_texture_settings = texture_settings.clone();
+    let mut new_object_settings = object_settings.clone();
+    let mut new_fog_settings = fog_settings.clone();
+    let mut new_time_of_day = time_of_day.clone();
+    let mut new_zone_settings = zone_settings.clone();
+    let mut new_walk_settings = walk_settings.clone();
+    let mut new_grid_settings = grid_settings.clone();
+
+    egui::Window::new("Settings")
+        .anchor(egui::Align2::RIGHT_BOTTOM, BevyVec2::new(0.0, 0.0))
+        .default_open(false)
+        .show(egui_context.ctx_mut(), |ui| {
+            egui::CollapsingHeader::new("Time of day").show(ui, |ui| {
+                ui.checkbox(&mut new_time_of_day.enabled, "Drive lighting from the time of day");
+                ui.checkbox(&mut new_time_of_day.light_tables, "Use the client's light tables");
+                let hour = new_time_of_day.hour;
+                ui.add(
+                    egui::Slider::new(&mut new_time_of_day.hour, 0.0..=24.0)
+                        .text(format!("{:02}:{:02}", hour as u32 % 24, (hour.fract() * 60.0) as u32)),
+                );
+                ui.checkbox(&mut new_time_of_day.animate, "Animate");
+                ui.add(egui::Slider::new(&mut new_time_of_day.day_length, 0.5..=60.0).text("Minutes per day"));
+            });
 
-                if textures.len() > 1 {
-                    for (i, t) in textures.into_iter().skip(1).enumerate() {
-                        let a = alpha_maps[i];
+            egui::CollapsingHeader::new("Walk").show(ui, |ui| {
+                ui.checkbox(&mut new_walk_settings.enabled, "Walk on the ground (space to jump)");
+                ui.add(egui::Slider::new(&mut new_walk_settings.eye_height, 0.5..=10.0).text("Eye height"));
+                ui.add(egui::Slider::new(&mut new_walk_settings.speed, 1.0..=50.0).text("Speed"));
+                ui.add(egui::Slider::new(&mut new_walk_settings.max_slope, 10.0..=90.0).text("Steepest slope"));
+                ui.add(egui::Slider::new(&mut new_walk_settings.jump_speed, 0.0..=30.0).text("Jump speed"));
+                ui.add(egui::Slider::new(&mut new_walk_settings.gravity, 1.0..=50.0).text("Gravity"));
+            });
 
-                        ui.horizontal(|ui| {
-                            ui.add(egui::widgets::Image::new(t, [128.0, 128.0]));
-                            ui.add(egui::widgets::Image::new(a, [128.0, 128.0]));
-                        });
+            egui::CollapsingHeader::new("Lighting").show(ui, |ui| {
+                ui.add(egui::Slider::new(&mut new_lighting.sun_azimuth, 0.0..=360.0).text("Sun azimuth"));
+                ui.add(egui::Slider::new(&mut new_lighting.sun_elevation, -10.0..=90.0).text("Sun elevation"));
+                ui.add(egui::Slider::new(&mut new_lighting.sun_illuminance, 0.0..=50_000.0).text("Sun illuminance"));
+                ui.horizontal(|ui| {
+                    ui.label("Sun colour");
+                    color_edit(ui, &mut new_lighting.sun_color);
+                });
+                ui.add(egui::Slider::new(&mut new_lighting.ambient_brightness, 0.0..=2.0).text("Ambient brightness"));
+                ui.horizontal(|ui| {
+                    ui.label("Ambient colour");
+                    color_edit(ui, &mut new_lighting.ambient_color);
+                });
+            });
+
+            egui::CollapsingHeader::new("Fog").show(ui, |ui| {
+                egui::ComboBox::from_label("Fog")
+                    .selected_text(new_fog_settings.mode.label())
+                    .show_ui(ui, |ui| {
+                        for mode in fog::FogMode::OPTIONS {
+                            ui.selectable_value(&mut new_fog_settings.mode, mode, mode.label());
+                        }
+                    });
+                ui.add(egui::Slider::new(&mut new_fog_settings.start, 0.0..=1.5).text("Start (x load radius)"));
+                ui.add(egui::Slider::new(&mut new_fog_settings.end, 0.0..=1.5).text("End (x load radius)"));
+                ui.horizontal(|ui| {
+                    ui.label("Fog colour");
+                    color_edit(ui, &mut new_fog_settings.color);
+                });
+                ui.label(format!("Load radius: {:.0} yards", fog::FogSettings::load_radius()));
+                if new_fog_settings.enabled() {
+                    ui.colored_label(Color32::LIGHT_YELLOW, "Doodads and WMOs aren't fogged, and stay sharp through it.");
+                }
+            });
+
+            egui::CollapsingHeader::new("Terrain").show(ui, |ui| {
+                ui.checkbox(&mut new_terrain_settings.specular, "Specular highlights from _s textures");
+                ui.checkbox(&mut new_terrain_settings.animate_layers, "Animate scrolling layers");
+                ui.checkbox(&mut new_terrain_settings.height_blending, "Height-based blending (MTXP tiles)");
+
+                egui::ComboBox::from_label("Texture filtering")
+                    .selected_text(new_texture_settings.filtering.label())
+                    .show_ui(ui, |ui| {
+                        for filtering in textures::TextureFiltering::OPTIONS {
+                            ui.selectable_value(&mut new_texture_settings.filtering, filtering, filtering.label());
+                        }
+                    });
+
+                ui.add_enabled(
+                    new_texture_settings.compression_supported,
+                    egui::Checkbox::new(&mut new_texture_settings.compression, "Keep DXT textures compressed (new tiles)"),
+                );
+                if !new_texture_settings.compression_supported {
+                    ui.colored_label(Color32::LIGHT_RED, "Adapter doesn't support BC texture compression, using RGBA.");
+                }
+            });
+
+            egui::CollapsingHeader::new("Objects").show(ui, |ui| {
+                ui.checkbox(&mut new_object_settings.doodads, "Doodads (M2)");
+                ui.checkbox(&mut new_object_settings.wmos, "World objects (WMO)");
+                ui.separator();
+                ui.checkbox(&mut new_object_settings.models, "Models");
+                ui.checkbox(&mut new_object_settings.bounding_boxes, "Bounding boxes (hover for filename)");
+            });
+
+            egui::CollapsingHeader::new("Grid").show(ui, |ui| {
+                ui.checkbox(&mut new_grid_settings.adt_borders, "ADT borders");
+                ui.checkbox(&mut new_grid_settings.chunk_borders, "Chunk borders");
+                ui.checkbox(&mut new_grid_settings.cells, "Cell grid (8x8)");
+                ui.checkbox(&mut new_grid_settings.labels, "Tile and chunk indices");
+            });
+
+            egui::CollapsingHeader::new("Zones").show(ui, |ui| {
+                ui.checkbox(&mut new_zone_settings.outlines, "Zone borders");
+                ui.label("Export borders as GeoJSON:");
+                ui.horizontal(|ui| {
+                    if ui.button("Loaded tiles").clicked() {
+                        new_zone_settings.export = Some(zones::ZoneScope::Loaded);
                     }
+                    if ui.add_enabled(!zone_export.is_reading(), egui::Button::new("All tiles")).clicked() {
+                        new_zone_settings.export = Some(zones::ZoneScope::AllTiles);
+                    }
+                });
+                if let Some(status) = &zone_export.status {
+                    ui.label(status);
                 }
             });
+        });
+
+    // Only write back on change, so systems watching for changes don't run every frame.
+    if *lighting != new_lighting {
+        *lighting = new_lighting;
+    }
+    if *terrain_settings != new_terrain_settings {
+        *terrain_settings = new_terrain_settings;
+    }
+    if *texture_settings != new_texture_settings {
+        *texture_settings = new_texture_settings;
+    }
+    if *object_settings != new_object_settings {
+        *object_settings = new_object_settings;
+    }
+    if *fog_settings != new_fog_settings {
+        *fog_settings = new_fog_settings;
+    }
+    if *time_of_day != new_time_of_day {
+        *time_of_day = new_time_of_day;
+    }
+    if *zone_settings != new_zone_settings {
+        *zone_settings = new_zone_settings;
+    }
+    if *walk_settings != new_walk_settings {
+        *walk_settings = new_walk_settings;
+    }
+    if *grid_settings != new_grid_settings {
+        *grid_settings = new_grid_settings;
+    }
+}
+
+fn color_edit(ui: &mut egui::Ui, color: &mut Color) {
+    let mut rgb = [color.r(), color.g(), color.b()];
+    if ui.color_edit_button_rgb(&mut rgb).changed() {
+        *color = Color::rgb(rgb[0], rgb[1], rgb[2]);
     }
 }
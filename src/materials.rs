@@ -1,10 +1,117 @@
-use bevy::{render::render_resource::{AsBindGroup, ShaderRef}, reflect::TypeUuid, prelude::{Handle, Image, Material, Vec2}};
+use bevy::{
+    pbr::{MaterialPipeline, MaterialPipelineKey},
+    render::{mesh::MeshVertexBufferLayout, render_resource::{AsBindGroup, CompareFunction, RenderPipelineDescriptor, ShaderRef, SpecializedMeshPipelineError}},
+    reflect::TypeUuid,
+    prelude::{AlphaMode, Assets, Color, Handle, Image, Material, Res, ResMut, Time, Vec2, Vec3, Vec4},
+};
+
+/// Runtime toggles for how terrain materials are shaded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TerrainSettings {
+    /// Use the alpha channel of `_s` textures as specular strength.
+    pub specular: bool,
+    /// Scroll layers that MCLY marks as animated, like lava and slime.
+    pub animate_layers: bool,
+    /// Blend layers using their `_h` height textures where the tile has them.
+    pub height_blending: bool,
+}
+
+impl Default for TerrainSettings {
+    fn default() -> Self {
+        Self {
+            specular: true,
+            animate_layers: true,
+            height_blending: true,
+        }
+    }
+}
+
+impl TerrainSettings {
+    pub fn specular_strength(&self) -> f32 {
+        if self.specular { 1.0 } else { 0.0 }
+    }
+
+    /// Height blend uniform for a material, falling back to linear blending without height textures.
+    pub fn height_blend(&self, has_height_textures: bool) -> f32 {
+        if self.height_blending && has_height_textures { 1.0 } else { 0.0 }
+    }
+}
+
+/// Push changes to `TerrainSettings` into every loaded terrain material.
+pub fn apply_terrain_settings(
+    settings: Res<TerrainSettings>,
+    mut materials: ResMut<Assets<CustomMaterial>>,
+) {
+    if !settings.is_changed() {
+        return
+    }
+
+    for (_, material) in materials.iter_mut() {
+        material.specular_strength = settings.specular_strength();
+        material.height_blend = settings.height_blend(material.has_height_textures);
+    }
+}
+
+/// Advance the time uniform of terrain materials with scrolling layers.
+pub fn animate_terrain_layers(
+    time: Res<Time>,
+    settings: Res<TerrainSettings>,
+    mut materials: ResMut<Assets<CustomMaterial>>,
+) {
+    if !settings.animate_layers {
+        return
+    }
+
+    // Only touch animated materials, as every modified material has its bind group rebuilt.
+    let animated: Vec<_> = materials.iter()
+        .filter(|(_, material)| material.is_animated())
+        .map(|(id, _)| id)
+        .collect();
+
+    let seconds = time.seconds_since_startup() as f32;
+    for id in animated {
+        if let Some(material) = materials.get_mut(&Handle::weak(id)) {
+            material.time = seconds;
+        }
+    }
+}
 
 #[derive(AsBindGroup, TypeUuid, Debug, Default, Clone)]
 #[uuid = "f5ec49f1-1a2e-4c3e-9f6f-836e54b1a576"]
 pub struct CustomMaterial {
     #[uniform(0)]
     pub base_positions: Vec2,
+    /// 1.0 for each layer whose texture carries specular strength in its alpha channel.
+    #[uniform(0)]
+    pub specular_layers: Vec4,
+    #[uniform(0)]
+    pub specular_strength: f32,
+    /// How many chunk units one repeat of each layer's texture covers.
+    #[uniform(0)]
+    pub layer_scales: Vec4,
+    /// Per-layer scrolling velocity in texture repeats per second, split into X and Y components.
+    #[uniform(0)]
+    pub layer_scroll_x: Vec4,
+    #[uniform(0)]
+    pub layer_scroll_y: Vec4,
+    /// Seconds since startup, only kept up to date for animated materials.
+    #[uniform(0)]
+    pub time: f32,
+    /// Per-layer MTXP height scale and offset, applied to the `_h` textures.
+    #[uniform(0)]
+    pub height_scales: Vec4,
+    #[uniform(0)]
+    pub height_offsets: Vec4,
+    /// 1.0 to blend layers by height, 0.0 for plain linear alpha blending.
+    #[uniform(0)]
+    pub height_blend: f32,
+    #[uniform(0)]
+    pub fog_color: Color,
+    /// Fog mode, start and end distance, from `FogSettings::params`.
+    #[uniform(0)]
+    pub fog_params: Vec4,
+    /// Whether every layer in use has a height texture, so height blending can be enabled.
+    pub has_height_textures: bool,
 
     #[texture(1)]
     #[sampler(2)]
@@ -30,6 +137,22 @@ pub struct CustomMaterial {
     #[texture(13)]
     #[sampler(14)]
     pub alpha_4: Option<Handle<Image>>,
+
+    // Height textures share their layer's sampler.
+    #[texture(15)]
+    pub height_1: Option<Handle<Image>>,
+    #[texture(16)]
+    pub height_2: Option<Handle<Image>>,
+    #[texture(17)]
+    pub height_3: Option<Handle<Image>>,
+    #[texture(18)]
+    pub height_4: Option<Handle<Image>>,
+}
+
+impl CustomMaterial {
+    pub fn is_animated(&self) -> bool {
+        self.layer_scroll_x != Vec4::ZERO || self.layer_scroll_y != Vec4::ZERO
+    }
 }
 
 impl Material for CustomMaterial {
@@ -41,6 +164,31 @@ impl Material for CustomMaterial {
 #[derive(AsBindGroup, TypeUuid, Debug, Default, Clone)]
 #[uuid = "af4a9d14-d090-4adb-9f11-adb40dd93ee9"]
 pub struct WaterMaterial {
+    /// Colour in the shallows, with the alpha channel as opacity.
+    #[uniform(0)]
+    pub color: Color,
+    /// Colour and opacity once the liquid is at least `fade_depths.y` deep.
+    #[uniform(0)]
+    pub deep_color: Color,
+    /// Depths (in yards) at which the shoreline has faded in, and at which `deep_color` is reached.
+    #[uniform(0)]
+    pub fade_depths: Vec2,
+    /// Light the liquid gives off on its own, like glowing magma.
+    #[uniform(0)]
+    pub emissive: Color,
+    #[uniform(0)]
+    pub time: f32,
+    /// 1.0 when `texture` holds a liquid animation frame, otherwise ripples are generated procedurally.
+    #[uniform(0)]
+    pub has_texture: f32,
+    #[uniform(0)]
+    pub fog_color: Color,
+    #[uniform(0)]
+    pub fog_params: Vec4,
+
+    #[texture(1)]
+    #[sampler(2)]
+    pub texture: Option<Handle<Image>>,
 }
 
 impl Material for WaterMaterial {
@@ -48,7 +196,74 @@ impl Material for WaterMaterial {
         "shaders/water.wgsl".into()
     }
 
-    fn alpha_mode(&self) -> bevy::prelude::AlphaMode {
-        bevy::prelude::AlphaMode::Blend
+    /// Opaque liquids like magma still fade out at the shoreline, so any fade needs blending.
+    fn alpha_mode(&self) -> AlphaMode {
+        let translucent = self.color.a() < 1.0 || self.deep_color.a() < 1.0;
+        if translucent || self.fade_depths.x > 0.0 {
+            AlphaMode::Blend
+        } else {
+            AlphaMode::Opaque
+        }
+    }
+}
+
+/// Flat coloured, lit and fogged ground for the WDL horizon.
+#[derive(AsBindGroup, TypeUuid, Debug, Default, Clone)]
+#[uuid = "0c8e5a41-7d26-4b3f-9e58-d1a6f2b47c93"]
+pub struct HorizonMaterial {
+    #[uniform(0)]
+    pub color: Color,
+    #[uniform(0)]
+    pub fog_color: Color,
+    #[uniform(0)]
+    pub fog_params: Vec4,
+}
+
+impl Material for HorizonMaterial {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/horizon.wgsl".into()
+    }
+}
+
+#[derive(AsBindGroup, TypeUuid, Debug, Default, Clone)]
+#[uuid = "5b0d7b9e-3c52-4f0e-a1a8-2f6b8c9d4e17"]
+pub struct SkyMaterial {
+    /// Colour straight up, blending down to `horizon_color`.
+    #[uniform(0)]
+    pub zenith_color: Color,
+    #[uniform(0)]
+    pub horizon_color: Color,
+    /// Colour of the sun's disc and the glow around it.
+    #[uniform(0)]
+    pub sun_color: Color,
+    /// Unit vector pointing towards the sun.
+    #[uniform(0)]
+    pub sun_direction: Vec3,
+}
+
+impl Material for SkyMaterial {
+    fn vertex_shader() -> ShaderRef {
+        "shaders/sky.wgsl".into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        "shaders/sky.wgsl".into()
+    }
+
+    // The camera sits inside the sky sphere, so its inward faces need drawing.
+    // The shader puts the sky on the far plane, at the depth the buffer is cleared to, so it has to pass an equal test
+    // and must not write depth itself.
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        _layout: &MeshVertexBufferLayout,
+        _key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        descriptor.primitive.cull_mode = None;
+        if let Some(depth_stencil) = descriptor.depth_stencil.as_mut() {
+            depth_stencil.depth_compare = CompareFunction::GreaterEqual;
+            depth_stencil.depth_write_enabled = false;
+        }
+        Ok(())
     }
 }
@@ -0,0 +1,110 @@
+//! Clicking the terrain to select a chunk and vertex for the inspector.
+
+use bevy::{prelude::*, utils::hashbrown::HashMap};
+use bevy_egui::EguiContext;
+use bevy_flycam::FlyCam;
+
+use wow_chunky::files;
+
+use crate::coordinates::ADTPosition;
+use crate::lines;
+use crate::ray::Ray;
+use crate::terrain::{TerrainPick, TerrainQuery};
+
+/// The highlight floats this far above the terrain, so it isn't hidden inside it.
+const HIGHLIGHT_LIFT: f32 = 0.5;
+const VERTEX_MARKER_SIZE: f32 = 0.6;
+
+/// The chunk picked by the last click on the terrain, shown in the inspector instead of the one under the camera.
+#[derive(Default)]
+pub struct TerrainSelection {
+    pub pick: Option<TerrainPick>,
+}
+
+/// Marker for the outline and vertex marker of the selected chunk.
+#[derive(Component)]
+pub struct SelectionHighlight;
+
+/// Select whatever terrain is under the cursor on a left click. Clicking the sky clears the selection.
+pub fn pick_terrain(
+    mouse: Res<Input<MouseButton>>,
+    mut egui_context: ResMut<EguiContext>,
+    windows: Res<Windows>,
+    camera: Query<(&Camera, &GlobalTransform), With<FlyCam>>,
+    terrain: TerrainQuery,
+    mut selection: ResMut<TerrainSelection>,
+) {
+    if !mouse.just_pressed(MouseButton::Left) || egui_context.ctx_mut().wants_pointer_input() {
+        return
+    }
+
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    let (camera, camera_transform) = camera.single();
+    let ray = match Ray::from_cursor(window, camera, camera_transform) {
+        Some(ray) => ray,
+        None => return,
+    };
+
+    selection.pick = terrain.pick(&ray);
+}
+
+/// Outline the selected chunk along its outer vertices, and mark the picked vertex.
+/// Clears the selection once its tile is unloaded.
+pub fn highlight_selection(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut selection: ResMut<TerrainSelection>,
+    adts: Res<HashMap<ADTPosition, Option<files::ADT>>>,
+    highlights: Query<Entity, With<SelectionHighlight>>,
+) {
+    let chunk = selection.pick.as_ref()
+        .map(|pick| adts.get(&pick.tile).and_then(|adt| adt.as_ref()).and_then(|adt| adt.mcnk.get(pick.chunk_index)));
+    if let Some(None) = chunk {
+        selection.pick = None;
+    }
+    if !selection.is_changed() {
+        return
+    }
+
+    for entity in &highlights {
+        commands.entity(entity).despawn();
+    }
+
+    let (pick, chunk) = match (&selection.pick, chunk.flatten()) {
+        (Some(pick), Some(chunk)) => (pick, chunk),
+        _ => return,
+    };
+
+    // Walk the outer vertices around the chunk's border: along the first row, down the last column, and back.
+    let outer = |row: usize, column: usize| chunk.mcvt.heights.get(row * 17 + column)
+        .map(|v| Vec3::new(v.x, v.z + HIGHLIGHT_LIFT, v.y));
+    let border: Vec<Vec3> = (0..8).map(|i| (0, i))
+        .chain((0..8).map(|i| (i, 8)))
+        .chain((0..8).map(|i| (8, 8 - i)))
+        .chain((0..8).map(|i| (8 - i, 0)))
+        .chain([(0, 0)])
+        .filter_map(|(row, column)| outer(row, column))
+        .collect();
+    let segments: Vec<(Vec3, Vec3)> = border.windows(2).map(|pair| (pair[0], pair[1])).collect();
+
+    let material = materials.add(lines::line_material(Color::rgb(0.2, 0.9, 1.0)));
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(lines::line_mesh(&segments)),
+            material: material.clone(),
+            ..default()
+        })
+        .insert(SelectionHighlight);
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(lines::unit_box_mesh()),
+            material,
+            transform: Transform::from_translation(pick.vertex_position).with_scale(Vec3::splat(VERTEX_MARKER_SIZE)),
+            ..default()
+        })
+        .insert(SelectionHighlight);
+}
@@ -0,0 +1,177 @@
+//! Wireframe boxes standing in for doodads and WMOs, labelled with their model on hover.
+
+use bevy::{prelude::*, utils::hashbrown::HashMap};
+use bevy_egui::{egui, EguiContext};
+use bevy_flycam::FlyCam;
+
+use crate::adt::AdtExtras;
+use crate::coordinates::{self, ADTPosition};
+use crate::doodads::{Doodad, ModelCache, ObjectSettings, SpawnedPlacements};
+use crate::lines;
+use crate::ray::Ray;
+use crate::wmo::Wmo;
+
+/// Smallest box size, so flat models still get a box that can be hovered.
+const MIN_BOX_SIZE: f32 = 0.01;
+
+/// A placement drawn as a unit box, scaled and rotated into place by its transform.
+#[derive(Component)]
+pub struct Placeholder {
+    pub label: String,
+}
+
+/// Mesh and materials shared by every placeholder.
+pub struct PlaceholderAssets {
+    mesh: Handle<Mesh>,
+    doodad_material: Handle<StandardMaterial>,
+    wmo_material: Handle<StandardMaterial>,
+}
+
+pub fn setup_placeholders(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(PlaceholderAssets {
+        mesh: meshes.add(lines::unit_box_mesh()),
+        doodad_material: materials.add(lines::line_material(Color::rgb(0.3, 1.0, 0.4))),
+        wmo_material: materials.add(lines::line_material(Color::rgb(1.0, 0.6, 0.2))),
+    });
+}
+
+/// Transform that scales a unit box to fit between two corners.
+fn box_transform(min: Vec3, max: Vec3) -> Transform {
+    Transform {
+        translation: (min + max) / 2.0,
+        scale: (max - min).abs().max(Vec3::splat(MIN_BOX_SIZE)),
+        ..default()
+    }
+}
+
+/// Spawn a box for each placement on a tile once its terrain has been rendered and bounding boxes are turned on.
+/// Doodad boxes come from the M2 header's bounds, WMO boxes from the extents stored in MODF.
+/// Like the models, placements listed by several tiles only get one box.
+#[allow(clippy::too_many_arguments)]
+pub fn render_placeholders(
+    mut commands: Commands,
+    assets: Res<PlaceholderAssets>,
+    mut model_cache: ResMut<ModelCache>,
+    extras_lookup: Res<HashMap<ADTPosition, AdtExtras>>,
+    mut adt_entities_lookup: ResMut<HashMap<ADTPosition, Vec<Entity>>>,
+    settings: Res<ObjectSettings>,
+    mut spawned_doodads: Local<SpawnedPlacements>,
+    mut spawned_wmos: Local<SpawnedPlacements>,
+) {
+    spawned_doodads.forget_unloaded(&adt_entities_lookup);
+    spawned_wmos.forget_unloaded(&adt_entities_lookup);
+    // Reading M2 headers for every doodad isn't free, so wait until the boxes are wanted.
+    if !settings.bounding_boxes {
+        return
+    }
+
+    for (position, entities) in adt_entities_lookup.iter_mut() {
+        if spawned_doodads.is_done(position) && spawned_wmos.is_done(position) {
+            continue;
+        }
+        let extras = match extras_lookup.get(position) {
+            Some(extras) => extras,
+            None => continue,
+        };
+
+        for placement in &extras.doodads {
+            if spawned_doodads.is_spawned(placement.unique_id) {
+                continue;
+            }
+            spawned_doodads.spawn(placement.unique_id, position);
+
+            let (min, max) = match model_cache.bounds(&placement.filename) {
+                Some(bounds) => bounds,
+                None => continue,
+            };
+
+            // Swap the bounds into Y up, like the model's geometry.
+            let local = box_transform(Vec3::new(min.x, min.z, min.y), Vec3::new(max.x, max.z, max.y));
+            let transform = coordinates::placement_transform(placement.position, placement.rotation, placement.scale)
+                .mul_transform(local);
+
+            let entity = commands
+                .spawn_bundle(PbrBundle {
+                    mesh: assets.mesh.clone(),
+                    material: assets.doodad_material.clone(),
+                    transform,
+                    visibility: Visibility { is_visible: settings.shows(true, false, true) },
+                    ..default()
+                })
+                .insert(Placeholder { label: placement.filename.clone() })
+                .insert(Doodad)
+                .id();
+            entities.push(entity);
+        }
+
+        for placement in &extras.wmos {
+            if spawned_wmos.is_spawned(placement.unique_id) {
+                continue;
+            }
+            spawned_wmos.spawn(placement.unique_id, position);
+
+            // MODF extents are already placed, so only need converting to Bevy's axes.
+            let a = coordinates::placement_to_bevy(placement.extents.0);
+            let b = coordinates::placement_to_bevy(placement.extents.1);
+
+            let entity = commands
+                .spawn_bundle(PbrBundle {
+                    mesh: assets.mesh.clone(),
+                    material: assets.wmo_material.clone(),
+                    transform: box_transform(a.min(b), a.max(b)),
+                    visibility: Visibility { is_visible: settings.shows(false, true, true) },
+                    ..default()
+                })
+                .insert(Placeholder { label: placement.filename.clone() })
+                .insert(Wmo)
+                .id();
+            entities.push(entity);
+        }
+
+        spawned_doodads.done(position);
+        spawned_wmos.done(position);
+    }
+}
+
+/// Show the filename of the nearest placeholder under the cursor.
+pub fn placeholder_hover(
+    mut egui_context: ResMut<EguiContext>,
+    windows: Res<Windows>,
+    camera: Query<(&Camera, &GlobalTransform), With<FlyCam>>,
+    placeholders: Query<(&Placeholder, &GlobalTransform, &ComputedVisibility)>,
+) {
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    // Don't label boxes behind the UI.
+    if egui_context.ctx_mut().wants_pointer_input() {
+        return
+    }
+
+    let (camera, camera_transform) = camera.single();
+    let ray = match Ray::from_cursor(window, camera, camera_transform) {
+        Some(ray) => ray,
+        None => return,
+    };
+
+    // Skip boxes the camera is inside of, or the surrounding WMO would always win.
+    let hovered = placeholders.iter()
+        .filter(|(_, _, visibility)| visibility.is_visible())
+        .filter_map(|(placeholder, transform, _)| {
+            ray.intersect_unit_box(transform)
+                .filter(|distance| *distance > 0.0)
+                .map(|distance| (distance, placeholder))
+        })
+        .min_by(|a, b| a.0.total_cmp(&b.0));
+
+    if let Some((_, placeholder)) = hovered {
+        egui::show_tooltip_at_pointer(egui_context.ctx_mut(), egui::Id::new("placeholder_hover"), |ui| {
+            ui.label(&placeholder.label);
+        });
+    }
+}
@@ -0,0 +1,65 @@
+//! Little-endian readers for file data that wow_chunky doesn't expose.
+
+use bevy::prelude::Vec3;
+
+pub fn read_u8(data: &[u8], offset: usize) -> Option<u8> {
+    data.get(offset).copied()
+}
+
+pub fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+    Some(u16::from_le_bytes(bytes.try_into().ok()?))
+}
+
+pub fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+pub fn read_f32(data: &[u8], offset: usize) -> Option<f32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(f32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// Read three consecutive f32s, as stored in the files (not converted to Bevy's axes).
+pub fn read_vec3(data: &[u8], offset: usize) -> Option<Vec3> {
+    Some(Vec3::new(
+        read_f32(data, offset)?,
+        read_f32(data, offset + 4)?,
+        read_f32(data, offset + 8)?,
+    ))
+}
+
+/// Iterator over the chunks of an IFF-style WoW file, yielding each chunk's magic and data.
+/// Magics are stored reversed on disk and are returned in their readable order, e.g. `*b"MCNK"`.
+pub struct Chunks<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+pub fn chunks(data: &[u8]) -> Chunks {
+    Chunks { data, offset: 0 }
+}
+
+impl<'a> Iterator for Chunks<'a> {
+    type Item = ([u8; 4], &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let header = self.data.get(self.offset..self.offset + 8)?;
+        let magic = [header[3], header[2], header[1], header[0]];
+        let size = read_u32(header, 4)? as usize;
+
+        let start = self.offset + 8;
+        let end = (start + size).min(self.data.len());
+        self.offset = start + size;
+
+        Some((magic, &self.data[start..end]))
+    }
+}
+
+/// Read a null-terminated string starting at `offset`.
+pub fn read_cstring(data: &[u8], offset: usize) -> Option<String> {
+    let bytes = data.get(offset..)?;
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    Some(String::from_utf8_lossy(&bytes[..end]).into_owned())
+}
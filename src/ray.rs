@@ -0,0 +1,93 @@
+//! Rays cast from the cursor, for picking things in the world.
+
+use bevy::prelude::*;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub direction: Vec3,
+}
+
+impl Ray {
+    /// Ray through the cursor, from the camera's near plane into the scene.
+    /// Returns `None` when the cursor is outside the window.
+    pub fn from_cursor(window: &Window, camera: &Camera, camera_transform: &GlobalTransform) -> Option<Self> {
+        let cursor = window.cursor_position()?;
+        let size = Vec2::new(window.width(), window.height());
+
+        // Window coordinates start at the bottom left, like NDC.
+        let ndc = cursor / size * 2.0 - Vec2::ONE;
+        let ndc_to_world = camera_transform.compute_matrix() * camera.projection_matrix().inverse();
+
+        // Bevy uses reversed Z with an infinite far plane, so the near plane is at 1 and anything below is further away.
+        let near = ndc_to_world.project_point3(ndc.extend(1.0));
+        let far = ndc_to_world.project_point3(ndc.extend(0.5));
+
+        Some(Self {
+            origin: near,
+            direction: (far - near).normalize_or_zero(),
+        })
+    }
+
+    pub fn at(&self, distance: f32) -> Vec3 {
+        self.origin + self.direction * distance
+    }
+
+    /// Distance along the ray to a triangle, hitting it from either side.
+    pub fn intersect_triangle(&self, corners: [Vec3; 3]) -> Option<f32> {
+        let edge_1 = corners[1] - corners[0];
+        let edge_2 = corners[2] - corners[0];
+        let p = self.direction.cross(edge_2);
+        let determinant = edge_1.dot(p);
+        if determinant.abs() < f32::EPSILON {
+            return None
+        }
+
+        let to_origin = self.origin - corners[0];
+        let u = to_origin.dot(p) / determinant;
+        if !(0.0..=1.0).contains(&u) {
+            return None
+        }
+
+        let q = to_origin.cross(edge_1);
+        let v = self.direction.dot(q) / determinant;
+        if v < 0.0 || u + v > 1.0 {
+            return None
+        }
+
+        let distance = edge_2.dot(q) / determinant;
+        if distance < 0.0 {
+            return None
+        }
+
+        Some(distance)
+    }
+
+    /// Distance along the ray to where it enters an axis aligned box, if it hits it at all.
+    pub fn intersect_aabb(&self, min: Vec3, max: Vec3) -> Option<f32> {
+        let inverse = self.direction.recip();
+        let t1 = (min - self.origin) * inverse;
+        let t2 = (max - self.origin) * inverse;
+
+        let near = t1.min(t2).max_element();
+        let far = t1.max(t2).min_element();
+
+        if far < near.max(0.0) {
+            return None
+        }
+
+        Some(near.max(0.0))
+    }
+
+    /// Distance along the ray to a unit cube (-0.5 to 0.5) placed by `transform`, measured in world units.
+    pub fn intersect_unit_box(&self, transform: &GlobalTransform) -> Option<f32> {
+        let to_local = transform.compute_matrix().inverse();
+        let local = Self {
+            origin: to_local.transform_point3(self.origin),
+            direction: to_local.transform_vector3(self.direction),
+        };
+
+        // The local direction isn't normalised, so distances along it are still world distances.
+        local.intersect_aabb(Vec3::splat(-0.5), Vec3::splat(0.5))
+    }
+}
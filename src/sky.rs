@@ -0,0 +1,197 @@
+use bevy::{pbr::NotShadowCaster, prelude::*};
+use bevy_flycam::FlyCam;
+
+use crate::fog::FogSettings;
+use crate::lighting::{mix_color, LightSample, LightTable, TerrainLighting};
+use crate::materials::SkyMaterial;
+
+/// Any size works, as the sky shader draws it behind everything else.
+const SKY_RADIUS: f32 = 100.0;
+
+/// Marker for the sphere the sky is drawn on.
+#[derive(Component)]
+pub struct Sky;
+
+/// Colours of the sky, which the time of day (or the client's light tables) picks between.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkyColors {
+    pub zenith: Color,
+    pub horizon: Color,
+    pub sun: Color,
+}
+
+/// Clock driving the sun's position and the sky and ambient colours.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeOfDay {
+    /// Apply the time of day to `TerrainLighting`. When off, the lighting sliders are left alone.
+    pub enabled: bool,
+    /// Hours since midnight, from 0 to 24.
+    pub hour: f32,
+    /// Advance the clock in real time.
+    pub animate: bool,
+    /// Real minutes for a full day when animating.
+    pub day_length: f32,
+    /// Take the sky, fog, sun and ambient colours from the client's light tables around the camera.
+    pub light_tables: bool,
+}
+
+impl Default for TimeOfDay {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            hour: 14.0,
+            animate: false,
+            day_length: 10.0,
+            light_tables: true,
+        }
+    }
+}
+
+impl TimeOfDay {
+    /// Sun elevation in degrees: rising at 6:00, highest at noon and setting at 18:00.
+    pub fn sun_elevation(&self) -> f32 {
+        70.0 * ((self.hour - 6.0) / 12.0 * std::f32::consts::PI).sin()
+    }
+
+    /// Sun azimuth in degrees, turning a full circle over the day.
+    pub fn sun_azimuth(&self) -> f32 {
+        (self.hour / 24.0 * 360.0 + 180.0) % 360.0
+    }
+
+    /// How much daylight there is, from 0 at night to 1 once the sun is well above the horizon.
+    fn daylight(&self) -> f32 {
+        let elevation = self.sun_elevation();
+        ((elevation + 4.0) / 24.0).clamp(0.0, 1.0)
+    }
+
+    /// How close the sun is to the horizon, peaking at sunrise and sunset.
+    fn twilight(&self) -> f32 {
+        (1.0 - self.sun_elevation().abs() / 12.0).clamp(0.0, 1.0)
+    }
+
+    pub fn sky_colors(&self) -> SkyColors {
+        let day = self.daylight();
+        let twilight = self.twilight();
+
+        let zenith = mix_color(Color::rgb(0.01, 0.02, 0.06), Color::rgb(0.22, 0.42, 0.78), day);
+        let horizon = mix_color(
+            mix_color(Color::rgb(0.05, 0.07, 0.12), Color::rgb(0.66, 0.78, 0.92), day),
+            Color::rgb(0.95, 0.55, 0.35),
+            twilight * 0.8,
+        );
+        let sun = mix_color(Color::rgb(1.0, 0.96, 0.88), Color::rgb(1.0, 0.55, 0.3), twilight);
+
+        SkyColors { zenith, horizon, sun }
+    }
+
+    /// Update the sun and ambient light for this time of day, keeping the user's other settings.
+    pub fn apply_to(&self, lighting: &mut TerrainLighting, sky: &SkyColors) {
+        let day = self.daylight();
+
+        lighting.sun_azimuth = self.sun_azimuth();
+        // Keep the light just above the horizon at night, so the moonlit terrain isn't lit from below.
+        lighting.sun_elevation = self.sun_elevation().max(5.0);
+        lighting.sun_color = if self.sun_elevation() > 0.0 { sky.sun } else { Color::rgb(0.55, 0.62, 0.9) };
+        lighting.sun_illuminance = 300.0 + day * 11_700.0;
+        lighting.ambient_color = mix_color(Color::rgb(0.3, 0.35, 0.6), sky.horizon, day);
+        lighting.ambient_brightness = 0.08 + day * 0.27;
+    }
+}
+
+pub fn setup_sky(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<SkyMaterial>>,
+) {
+    commands
+        .spawn_bundle(MaterialMeshBundle {
+            mesh: meshes.add(Mesh::from(shape::UVSphere { radius: SKY_RADIUS, sectors: 32, stacks: 16 })),
+            material: materials.add(SkyMaterial::default()),
+            ..default()
+        })
+        .insert(Sky)
+        .insert(NotShadowCaster);
+}
+
+pub fn advance_time_of_day(
+    time: Res<Time>,
+    mut time_of_day: ResMut<TimeOfDay>,
+) {
+    if !time_of_day.animate {
+        return
+    }
+
+    let hours = time.delta_seconds() * 24.0 / (time_of_day.day_length.max(0.1) * 60.0);
+    time_of_day.hour = (time_of_day.hour + hours) % 24.0;
+}
+
+/// Fog colours closer than this are left alone, so moving through a light's falloff doesn't touch every material each frame.
+const FOG_COLOR_THRESHOLD: f32 = 2.0 / 255.0;
+
+/// Push the time of day into the lighting and the sky's material.
+/// With light tables on, the colours come from the lights around the camera instead, and follow it as it moves.
+#[allow(clippy::too_many_arguments)]
+pub fn apply_time_of_day(
+    time_of_day: Res<TimeOfDay>,
+    light_table: Res<LightTable>,
+    camera: Query<&Transform, With<FlyCam>>,
+    mut lighting: ResMut<TerrainLighting>,
+    mut fog: ResMut<FogSettings>,
+    sky: Query<&Handle<SkyMaterial>, With<Sky>>,
+    mut sky_materials: ResMut<Assets<SkyMaterial>>,
+    mut last_sample: Local<Option<LightSample>>,
+) {
+    let sample = match camera.get_single() {
+        Ok(camera) if time_of_day.light_tables => light_table.sample(camera.translation, time_of_day.hour),
+        _ => None,
+    };
+    let sample_changed = sample != *last_sample;
+    if !time_of_day.is_changed() && !lighting.is_changed() && !sample_changed {
+        return
+    }
+    *last_sample = sample;
+
+    let colors = match sample {
+        Some(sample) => SkyColors { zenith: sample.sky_top, horizon: sample.horizon, sun: sample.sun },
+        None => time_of_day.sky_colors(),
+    };
+    if time_of_day.enabled && (time_of_day.is_changed() || sample_changed) {
+        time_of_day.apply_to(&mut lighting, &colors);
+        if let Some(sample) = sample {
+            lighting.sun_color = sample.diffuse;
+            lighting.ambient_color = sample.ambient;
+        }
+    }
+
+    if let Some(sample) = sample {
+        let difference = (Vec4::from(fog.color) - Vec4::from(sample.fog)).abs().max_element();
+        if fog.enabled() && difference > FOG_COLOR_THRESHOLD {
+            fog.color = sample.fog;
+        }
+    }
+
+    for handle in &sky {
+        if let Some(material) = sky_materials.get_mut(handle) {
+            material.zenith_color = colors.zenith;
+            material.horizon_color = colors.horizon;
+            material.sun_color = lighting.sun_color;
+            // Lights shine down their -Z axis, so the sun is found along +Z.
+            material.sun_direction = lighting.sun_rotation() * Vec3::Z;
+        }
+    }
+}
+
+/// Keep the sky centred on the camera.
+pub fn follow_camera(
+    camera: Query<&Transform, (With<FlyCam>, Without<Sky>)>,
+    mut sky: Query<&mut Transform, With<Sky>>,
+) {
+    let camera = match camera.get_single() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+
+    for mut transform in &mut sky {
+        transform.translation = camera.translation;
+    }
+}
@@ -0,0 +1,337 @@
+//! Queries against the loaded terrain, interpolated across the same triangles as `create_ground_mesh`.
+
+use std::marker::PhantomData;
+
+use bevy::{ecs::system::SystemParam, prelude::*, utils::hashbrown::HashMap};
+
+use wow_chunky::{chunks, files};
+
+use crate::adt::{AdtExtras, ChunkExtras};
+use crate::coordinates::{self, ADTPosition, WorldPosition};
+use crate::liquid::{LIQUID_TILES, LIQUID_VERTICES};
+use crate::ray::Ray;
+
+/// MCVT stores rows of 9 outer vertices followed by 8 inner ones.
+const MCVT_ROW: usize = 17;
+const MCVT_OUTER_ROW: usize = 9;
+const CHUNK_CELLS: usize = 8;
+/// Alpha maps are 64x64, covering the chunk like its cells.
+const ALPHA_MAP_SIZE: usize = 64;
+
+/// Triangles between a chunk's MCVT vertices: four per cell, meeting at its inner vertex.
+pub fn ground_indices() -> Vec<u32> {
+    let mut indices: Vec<u32> = Vec::new();
+    for x in 0..8 {
+        for y in 0..8 {
+            let current_index = y * 17 + x;
+
+            indices.push(current_index + 1);
+            indices.push(current_index + 9);
+            indices.push(current_index);
+
+            indices.push(current_index + 9);
+            indices.push(current_index + 17);
+            indices.push(current_index);
+
+            indices.push(current_index + 18);
+            indices.push(current_index + 17);
+            indices.push(current_index + 9);
+
+            indices.push(current_index + 18);
+            indices.push(current_index + 9);
+            indices.push(current_index + 1);
+        }
+    }
+
+    indices
+}
+
+/// Read access to the loaded terrain, for systems that need to know about the ground at a position.
+/// Positions are in Bevy space, and only X and Z are used.
+#[derive(SystemParam)]
+pub struct TerrainQuery<'w, 's> {
+    adts: Res<'w, HashMap<ADTPosition, Option<files::ADT>>>,
+    extras_lookup: Res<'w, HashMap<ADTPosition, AdtExtras>>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+/// Everything known about the ground at a position.
+#[derive(Debug, Clone)]
+pub struct TerrainSample {
+    pub height: f32,
+    /// Normal of the triangle under the position, in Bevy's axes.
+    pub normal: Vec3,
+    /// AreaTable ID of the chunk.
+    pub area_id: u32,
+    /// The chunk's texture layers, bottom first.
+    pub layers: Vec<TextureLayerSample>,
+    /// Height of the highest liquid surface covering the position, if any.
+    pub liquid_level: Option<f32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TextureLayerSample {
+    /// Client path of the texture, from MTEX.
+    pub filename: String,
+    /// Opacity of the layer over the ones below it, from its alpha map. Always 1 for the first layer.
+    pub alpha: f32,
+}
+
+/// Where a ray hit the loaded terrain.
+#[derive(Debug, Clone)]
+pub struct TerrainPick {
+    pub tile: ADTPosition,
+    /// Index of the chunk in its tile's MCNKs.
+    pub chunk_index: usize,
+    /// Point that was hit, in Bevy space.
+    pub position: Vec3,
+    /// MCVT index of the hit triangle's vertex nearest the hit, and its position in Bevy space.
+    pub vertex: usize,
+    pub vertex_position: Vec3,
+}
+
+/// A loaded chunk, with what else the tile knows about it.
+struct LoadedChunk<'a> {
+    chunk: &'a chunks::adt::MCNK,
+    extras: Option<&'a ChunkExtras>,
+    textures: &'a [String],
+}
+
+/// One of the four triangles a cell is split into around its inner vertex, in WoW's axes.
+struct GroundTriangle {
+    corners: [Vec3; 3],
+    /// Barycentric weights of the query position.
+    weights: Vec3,
+}
+
+impl GroundTriangle {
+    fn height(&self) -> f32 {
+        self.weights.dot(Vec3::new(self.corners[0].z, self.corners[1].z, self.corners[2].z))
+    }
+
+    /// Upward normal of the triangle, in Bevy's axes.
+    fn normal(&self) -> Vec3 {
+        let normal = (self.corners[1] - self.corners[0]).cross(self.corners[2] - self.corners[0]).normalize_or_zero();
+        let normal = if normal.z < 0.0 { -normal } else { normal };
+        Vec3::new(normal.x, normal.z, normal.y)
+    }
+}
+
+impl<'w, 's> TerrainQuery<'w, 's> {
+    /// The loaded chunk containing a position.
+    fn chunk(&self, position: Vec3) -> Option<LoadedChunk<'_>> {
+        let world = WorldPosition::from(position);
+        let tile = ADTPosition::from(&world);
+        let adt = self.adts.get(&tile)?.as_ref()?;
+
+        // MCNK positions are the chunk's corner with the largest X and Y.
+        let inside = |corner: f32, value: f32| (0.0..=coordinates::CHUNK_SIZE).contains(&(corner - value));
+        let (index, chunk) = adt.mcnk.iter().enumerate()
+            .find(|(_, chunk)| inside(chunk.position.x, world.x) && inside(chunk.position.y, world.y))?;
+
+        Some(LoadedChunk {
+            chunk,
+            extras: self.extras_lookup.get(&tile).and_then(|extras| extras.chunks.get(index)),
+            textures: adt.mtex.as_ref().map_or(&[][..], |mtex| mtex.filenames.as_slice()),
+        })
+    }
+
+    /// Row and column of a position within a chunk's 8x8 cells, as fractions.
+    /// Found from the directions MCVT's rows and columns run in, rather than assuming which is X.
+    fn cell_position(chunk: &chunks::adt::MCNK, position: Vec3) -> Option<(f32, f32)> {
+        let world = WorldPosition::from(position);
+        let origin = chunk.mcvt.heights.first()?;
+        let row_end = chunk.mcvt.heights.get(MCVT_ROW)?;
+        let column_end = chunk.mcvt.heights.get(1)?;
+
+        let offset = Vec2::new(world.x - origin.x, world.y - origin.y);
+        let row_step = Vec2::new(row_end.x - origin.x, row_end.y - origin.y);
+        let column_step = Vec2::new(column_end.x - origin.x, column_end.y - origin.y);
+
+        Some((
+            (offset.dot(row_step) / row_step.length_squared()).clamp(0.0, CHUNK_CELLS as f32),
+            (offset.dot(column_step) / column_step.length_squared()).clamp(0.0, CHUNK_CELLS as f32),
+        ))
+    }
+
+    fn triangle(chunk: &chunks::adt::MCNK, position: Vec3) -> Option<GroundTriangle> {
+        let vertex = |index: usize| chunk.mcvt.heights.get(index).map(|v| Vec3::new(v.x, v.y, v.z));
+        let outer = |row: usize, column: usize| vertex(row * MCVT_ROW + column);
+        let inner = |row: usize, column: usize| vertex(row * MCVT_ROW + MCVT_OUTER_ROW + column);
+
+        let world = WorldPosition::from(position);
+        let point = Vec2::new(world.x, world.y);
+        let (row, column) = Self::cell_position(chunk, position)?;
+        let cell_row = (row as usize).min(CHUNK_CELLS - 1);
+        let cell_column = (column as usize).min(CHUNK_CELLS - 1);
+        let (a, b) = (row - cell_row as f32, column - cell_column as f32);
+        let centre = inner(cell_row, cell_column)?;
+        let [top_left, top_right, bottom_left, bottom_right] = [
+            outer(cell_row, cell_column)?,
+            outer(cell_row, cell_column + 1)?,
+            outer(cell_row + 1, cell_column)?,
+            outer(cell_row + 1, cell_column + 1)?,
+        ];
+
+        // The diagonals through the inner vertex split the cell into top, bottom, left and right triangles.
+        let corners = if a <= b && a <= 1.0 - b {
+            [top_left, top_right, centre]
+        } else if a >= b && a >= 1.0 - b {
+            [bottom_left, bottom_right, centre]
+        } else if b < a {
+            [top_left, bottom_left, centre]
+        } else {
+            [top_right, bottom_right, centre]
+        };
+
+        let (p0, p1, p2) = (corners[0].truncate(), corners[1].truncate(), corners[2].truncate());
+        let area = (p1 - p0).perp_dot(p2 - p0);
+        if area.abs() < f32::EPSILON {
+            return None
+        }
+        let w1 = (point - p0).perp_dot(p2 - p0) / area;
+        let w2 = (p1 - p0).perp_dot(point - p0) / area;
+
+        Some(GroundTriangle { corners, weights: Vec3::new(1.0 - w1 - w2, w1, w2) })
+    }
+
+    /// Opacity of each texture layer at a position, from the chunk's alpha maps.
+    fn layers(loaded: &LoadedChunk, position: Vec3) -> Vec<TextureLayerSample> {
+        let (row, column) = Self::cell_position(loaded.chunk, position).unwrap_or_default();
+        let texel = |value: f32| ((value / CHUNK_CELLS as f32 * ALPHA_MAP_SIZE as f32) as usize).min(ALPHA_MAP_SIZE - 1);
+        let index = texel(row) * ALPHA_MAP_SIZE + texel(column);
+
+        loaded.chunk.mcly.layers.iter().enumerate()
+            .map(|(i, layer)| {
+                // The first layer has no alpha map, so alpha map `i - 1` belongs to layer `i`.
+                let alpha = match i.checked_sub(1) {
+                    None => 1.0,
+                    Some(alpha_index) => loaded.chunk.mcal.layers.get(alpha_index)
+                        .and_then(|alpha| alpha.alpha_map.get(index))
+                        .map_or(0.0, |alpha| *alpha as f32 / 255.0),
+                };
+
+                TextureLayerSample {
+                    filename: loaded.textures.get(layer.texture_id as usize).cloned().unwrap_or_default(),
+                    alpha,
+                }
+            })
+            .collect()
+    }
+
+    /// Height of the highest liquid over a position, interpolated across its liquid tile.
+    /// Liquid rows run along X from the chunk's corner, like `create_water_mesh` lays them out.
+    fn liquid_level(loaded: &LoadedChunk, position: Vec3) -> Option<f32> {
+        let world = WorldPosition::from(position);
+        let spread = coordinates::CHUNK_SIZE / LIQUID_TILES as f32;
+        let row = ((loaded.chunk.position.x - world.x) / spread).clamp(0.0, LIQUID_TILES as f32);
+        let column = ((loaded.chunk.position.y - world.y) / spread).clamp(0.0, LIQUID_TILES as f32);
+        let tile_row = (row as usize).min(LIQUID_TILES - 1);
+        let tile_column = (column as usize).min(LIQUID_TILES - 1);
+        let (a, b) = (row - tile_row as f32, column - tile_column as f32);
+
+        // Chunks whose MCLQ couldn't be read still get the flat plane `create_chunk_meshes` falls back to.
+        let liquids = loaded.extras.map_or(&[][..], |extras| extras.liquids.as_slice());
+        let flags = &loaded.chunk.flags;
+        if liquids.is_empty() && (flags.lq_ocean || flags.lq_magma || flags.lq_river) {
+            return Some(loaded.chunk.mclq.height.max)
+        }
+
+        liquids.iter()
+            .filter(|liquid| liquid.renders_tile(tile_row, tile_column) && liquid.heights.len() == LIQUID_VERTICES * LIQUID_VERTICES)
+            .map(|liquid| {
+                let top = liquid.height(tile_row, tile_column) * (1.0 - b) + liquid.height(tile_row, tile_column + 1) * b;
+                let bottom = liquid.height(tile_row + 1, tile_column) * (1.0 - b) + liquid.height(tile_row + 1, tile_column + 1) * b;
+                top * (1.0 - a) + bottom * a
+            })
+            .reduce(f32::max)
+    }
+
+    /// Height of the ground at a position, or `None` if its tile isn't loaded.
+    pub fn height(&self, position: Vec3) -> Option<f32> {
+        let loaded = self.chunk(position)?;
+        Self::triangle(loaded.chunk, position).map(|triangle| triangle.height())
+    }
+
+    /// Normal of the ground's triangle at a position, pointing up.
+    pub fn normal(&self, position: Vec3) -> Option<Vec3> {
+        let loaded = self.chunk(position)?;
+        Self::triangle(loaded.chunk, position).map(|triangle| triangle.normal())
+    }
+
+    /// AreaTable ID of the chunk at a position.
+    pub fn area_id(&self, position: Vec3) -> Option<u32> {
+        self.chunk(position)?.extras.map(|extras| extras.area_id)
+    }
+
+    /// Height, normal, area, texture layers and liquid level at a position, or `None` if its tile isn't loaded.
+    pub fn sample(&self, position: Vec3) -> Option<TerrainSample> {
+        let loaded = self.chunk(position)?;
+        let triangle = Self::triangle(loaded.chunk, position)?;
+
+        Some(TerrainSample {
+            height: triangle.height(),
+            normal: triangle.normal(),
+            area_id: loaded.extras.map_or(0, |extras| extras.area_id),
+            layers: Self::layers(&loaded, position),
+            liquid_level: Self::liquid_level(&loaded, position),
+        })
+    }
+
+    /// The nearest point on the loaded terrain along a ray, tested against every triangle of the ground meshes.
+    pub fn pick(&self, ray: &Ray) -> Option<TerrainPick> {
+        let indices = ground_indices();
+        let mut nearest: Option<(f32, TerrainPick)> = None;
+
+        for (tile, adt) in self.adts.iter() {
+            let adt = match adt {
+                Some(adt) => adt,
+                None => continue,
+            };
+
+            for (chunk_index, chunk) in adt.mcnk.iter().enumerate() {
+                let vertices: Vec<Vec3> = chunk.mcvt.heights.iter().map(|v| Vec3::new(v.x, v.z, v.y)).collect();
+                if vertices.is_empty() {
+                    continue;
+                }
+
+                // Skip chunks whose bounds are missed, or are further away than what's already been hit.
+                let (min, max) = vertices.iter()
+                    .fold((Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)), |(min, max), v| (min.min(*v), max.max(*v)));
+                match ray.intersect_aabb(min, max) {
+                    Some(entry) if nearest.as_ref().map_or(true, |(distance, _)| entry < *distance) => {}
+                    _ => continue,
+                }
+
+                for triangle in indices.chunks_exact(3) {
+                    let corner_indices = [triangle[0] as usize, triangle[1] as usize, triangle[2] as usize];
+                    let corners = match corner_indices.map(|index| vertices.get(index).copied()) {
+                        [Some(a), Some(b), Some(c)] => [a, b, c],
+                        _ => continue,
+                    };
+
+                    let distance = match ray.intersect_triangle(corners) {
+                        Some(distance) if nearest.as_ref().map_or(true, |(nearest, _)| distance < *nearest) => distance,
+                        _ => continue,
+                    };
+
+                    let position = ray.at(distance);
+                    let vertex = corner_indices.into_iter()
+                        .min_by(|a, b| vertices[*a].distance_squared(position).total_cmp(&vertices[*b].distance_squared(position)))
+                        .unwrap_or(corner_indices[0]);
+
+                    nearest = Some((distance, TerrainPick {
+                        tile: tile.clone(),
+                        chunk_index,
+                        position,
+                        vertex,
+                        vertex_position: vertices[vertex],
+                    }));
+                }
+            }
+        }
+
+        nearest.map(|(_, pick)| pick)
+    }
+}
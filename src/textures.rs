@@ -0,0 +1,328 @@
+use std::num::NonZeroU8;
+use std::path::{Path, PathBuf};
+
+use bevy::{
+    asset::HandleId,
+    prelude::*,
+    render::{render_resource::{Extent3d, SamplerDescriptor, TextureFormat}, renderer::RenderDevice, texture::{CompressedImageFormats, ImageSampler}},
+    utils::hashbrown::HashMap,
+};
+
+use wgpu_types::{AddressMode, FilterMode};
+
+use wow_chunky::files;
+
+use crate::materials::CustomMaterial;
+use crate::raw;
+
+/// Sampling quality used for terrain textures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureFiltering {
+    Bilinear,
+    Trilinear,
+    Anisotropic(u8),
+}
+
+impl TextureFiltering {
+    pub const OPTIONS: [TextureFiltering; 6] = [
+        TextureFiltering::Bilinear,
+        TextureFiltering::Trilinear,
+        TextureFiltering::Anisotropic(2),
+        TextureFiltering::Anisotropic(4),
+        TextureFiltering::Anisotropic(8),
+        TextureFiltering::Anisotropic(16),
+    ];
+
+    pub fn label(&self) -> String {
+        match self {
+            TextureFiltering::Bilinear => "Bilinear".to_string(),
+            TextureFiltering::Trilinear => "Trilinear".to_string(),
+            TextureFiltering::Anisotropic(level) => format!("Anisotropic {}x", level),
+        }
+    }
+
+    /// Repeating sampler for this filtering quality, to allow for easier tiling.
+    pub fn sampler(&self) -> ImageSampler {
+        let mipmap_filter = match self {
+            TextureFiltering::Bilinear => FilterMode::Nearest,
+            _ => FilterMode::Linear,
+        };
+
+        let anisotropy_clamp = match self {
+            TextureFiltering::Anisotropic(level) => NonZeroU8::new(*level),
+            _ => None,
+        };
+
+        ImageSampler::Descriptor(SamplerDescriptor {
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter,
+            address_mode_u: AddressMode::Repeat,
+            address_mode_v: AddressMode::Repeat,
+            anisotropy_clamp,
+            ..default()
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextureSettings {
+    pub filtering: TextureFiltering,
+    /// Keep DXT BLPs block-compressed on the GPU instead of decompressing them to RGBA.
+    pub compression: bool,
+    /// Whether the adapter can sample BC1/BC2/BC3 textures. Filled in at startup.
+    pub compression_supported: bool,
+}
+
+impl Default for TextureSettings {
+    fn default() -> Self {
+        Self {
+            filtering: TextureFiltering::Anisotropic(8),
+            compression: true,
+            compression_supported: false,
+        }
+    }
+}
+
+impl TextureSettings {
+    pub fn use_compression(&self) -> bool {
+        self.compression && self.compression_supported
+    }
+}
+
+/// Check whether the adapter we ended up with (software adapters included) supports BC textures.
+pub fn detect_texture_compression(
+    render_device: Res<RenderDevice>,
+    mut settings: ResMut<TextureSettings>,
+) {
+    let formats = CompressedImageFormats::from_features(render_device.features());
+    settings.compression_supported = formats.contains(CompressedImageFormats::BC);
+}
+
+/// Size of an RGBA8 mip level, in bytes.
+fn mip_size(width: u32, height: u32, level: usize) -> usize {
+    let width = (width >> level).max(1) as usize;
+    let height = (height >> level).max(1) as usize;
+
+    width * height * 4
+}
+
+/// Box filter an RGBA8 image down to the next mip level.
+fn downsample(width: u32, height: u32, data: &[u8]) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    let (new_width, new_height) = ((width / 2).max(1), (height / 2).max(1));
+
+    let mut out = Vec::with_capacity(new_width * new_height * 4);
+    for y in 0..new_height {
+        for x in 0..new_width {
+            let xs = [(x * 2).min(width - 1), (x * 2 + 1).min(width - 1)];
+            let ys = [(y * 2).min(height - 1), (y * 2 + 1).min(height - 1)];
+
+            for channel in 0..4 {
+                let mut total = 0_u32;
+                for sy in ys {
+                    for sx in xs {
+                        total += data[(sy * width + sx) * 4 + channel] as u32;
+                    }
+                }
+                out.push((total / 4) as u8);
+            }
+        }
+    }
+
+    out
+}
+
+/// Build a mipmapped RGBA8 image from the levels a BLP provides.
+/// Any levels that are missing or truncated are generated from the last valid one.
+/// Returns `None` if even the top level is incomplete.
+pub fn generate_image_from_mipmaps(width: u32, height: u32, mipmaps: &[&[u8]], filtering: TextureFiltering) -> Option<Image> {
+    let level_count = (32 - width.max(height).leading_zeros()) as usize;
+
+    let mut levels: Vec<Vec<u8>> = mipmaps.iter()
+        .take(level_count)
+        .enumerate()
+        .take_while(|(i, data)| data.len() >= mip_size(width, height, *i))
+        .map(|(i, data)| data[..mip_size(width, height, i)].to_vec())
+        .collect();
+    if levels.is_empty() {
+        return None
+    }
+
+    while levels.len() < level_count {
+        let level = levels.len() - 1;
+        let next = downsample((width >> level).max(1), (height >> level).max(1), &levels[level]);
+        levels.push(next);
+    }
+
+    let mut tex = Image::default();
+    tex.texture_descriptor.size = Extent3d {
+        width,
+        height,
+        ..default()
+    };
+    tex.texture_descriptor.format = TextureFormat::Rgba8Unorm;
+    tex.texture_descriptor.mip_level_count = levels.len() as u32;
+    tex.data = levels.concat();
+    tex.sampler_descriptor = filtering.sampler();
+
+    Some(tex)
+}
+
+/// Build a block-compressed image straight from a BLP2 file's DXT data.
+/// Returns `None` when the BLP isn't DXT compressed or can't be uploaded as-is,
+/// in which case the caller should fall back to decompressing it.
+/// Only the mip levels stored in the file are uploaded, as BC data can't be regenerated here.
+pub fn compressed_image_from_blp(path: &Path, filtering: TextureFiltering) -> Option<Image> {
+    let data = std::fs::read(path).ok()?;
+    if data.get(0..4)? != b"BLP2" {
+        return None
+    }
+
+    let compression = raw::read_u8(&data, 0x08)?;
+    let alpha_type = raw::read_u8(&data, 0x0A)?;
+    let has_mips = raw::read_u8(&data, 0x0B)? != 0;
+    let width = raw::read_u32(&data, 0x0C)?;
+    let height = raw::read_u32(&data, 0x10)?;
+
+    // Compression type 2 is DXT, with the alpha type picking the variant.
+    if compression != 2 {
+        return None
+    }
+    let (format, block_size) = match alpha_type {
+        0 => (TextureFormat::Bc1RgbaUnorm, 8),
+        1 => (TextureFormat::Bc2RgbaUnorm, 16),
+        7 => (TextureFormat::Bc3RgbaUnorm, 16),
+        _ => return None,
+    };
+
+    // Block-compressed textures need their top level to be a whole number of 4x4 blocks.
+    if width == 0 || height == 0 || width % 4 != 0 || height % 4 != 0 {
+        return None
+    }
+
+    let level_count = if has_mips {
+        (32 - width.max(height).leading_zeros()).min(16) as usize
+    } else {
+        1
+    };
+
+    let mut levels: Vec<&[u8]> = Vec::new();
+    for level in 0..level_count {
+        let offset = raw::read_u32(&data, 0x14 + level * 4)? as usize;
+        let size = raw::read_u32(&data, 0x54 + level * 4)? as usize;
+
+        let blocks_wide = ((width >> level).max(1) as usize + 3) / 4;
+        let blocks_high = ((height >> level).max(1) as usize + 3) / 4;
+        let expected = blocks_wide * blocks_high * block_size;
+
+        match data.get(offset..offset + expected) {
+            Some(level_data) if offset != 0 && size >= expected => levels.push(level_data),
+            _ => break,
+        }
+    }
+
+    if levels.is_empty() {
+        return None
+    }
+
+    let mut tex = Image::default();
+    tex.texture_descriptor.size = Extent3d {
+        width,
+        height,
+        ..default()
+    };
+    tex.texture_descriptor.format = format;
+    tex.texture_descriptor.mip_level_count = levels.len() as u32;
+    tex.data = levels.concat();
+    tex.sampler_descriptor = filtering.sampler();
+
+    Some(tex)
+}
+
+/// Load a BLP into an image, keeping it compressed when the settings allow.
+/// Returns `None` if the BLP is missing or can't be read, so callers can fall back to an untextured material.
+pub fn load_blp_image(path: &Path, settings: &TextureSettings) -> Option<Image> {
+    let compressed = if settings.use_compression() {
+        compressed_image_from_blp(path, settings.filtering)
+    } else {
+        None
+    };
+
+    compressed.or_else(|| {
+        let blp = match files::BLP::try_from(path.to_path_buf()) {
+            Ok(blp) => blp,
+            Err(_) => {
+                warn!("Couldn't read BLP {:?}", path);
+                return None
+            }
+        };
+
+        let mipmaps: Vec<&[u8]> = blp.mipmaps.iter().map(|m| m.decompressed.as_slice()).collect();
+        generate_image_from_mipmaps(blp.width, blp.height, &mipmaps, settings.filtering)
+    })
+}
+
+/// Load a terrain BLP, preferring its `_s` variant.
+/// Returns the texture along with whether its alpha channel holds specular strength, or `None` if it can't be loaded.
+pub fn process_blp(raw_filename: &str, textures: &mut ResMut<Assets<Image>>, settings: &TextureSettings) -> Option<(Handle<Image>, bool)> {
+    let specular_filename = format!(
+        "./test_data/{}_s.blp",
+        raw_filename.replace('\\', "/").replace(".blp", "")
+    );
+    let normal_filename = format!("./test_data/{}", raw_filename.replace('\\', "/"));
+
+    let specular_path = PathBuf::from(&specular_filename);
+    let normal_path = PathBuf::from(&normal_filename);
+
+    let has_specular = specular_path.exists();
+    let path = if has_specular {
+        specular_path
+    } else {
+        normal_path
+    };
+
+    let texture = load_blp_image(&path, settings)?;
+
+    Some((textures.add(texture), has_specular))
+}
+
+/// Load the `_h` height texture for a terrain BLP, if there is one.
+pub fn process_height_blp(raw_filename: &str, textures: &mut ResMut<Assets<Image>>, settings: &TextureSettings) -> Option<Handle<Image>> {
+    let height_path = PathBuf::from(format!(
+        "./test_data/{}_h.blp",
+        raw_filename.replace('\\', "/").replace(".blp", "")
+    ));
+
+    if !height_path.exists() {
+        return None
+    }
+
+    load_blp_image(&height_path, settings).map(|texture| textures.add(texture))
+}
+
+/// Push filtering changes to every terrain texture that's already loaded.
+pub fn apply_texture_settings(
+    settings: Res<TextureSettings>,
+    blp_lookup: Res<HashMap<(String, usize), (Handle<Image>, bool)>>,
+    height_lookup: Res<HashMap<(String, usize), Handle<Image>>>,
+    mut images: ResMut<Assets<Image>>,
+    mut materials: ResMut<Assets<CustomMaterial>>,
+) {
+    if !settings.is_changed() {
+        return
+    }
+
+    let diffuse = blp_lookup.values().map(|(handle, _)| handle);
+    for handle in diffuse.chain(height_lookup.values()) {
+        if let Some(image) = images.get_mut(handle) {
+            image.sampler_descriptor = settings.filtering.sampler();
+        }
+    }
+
+    let ids: Vec<HandleId> = materials.ids().collect();
+    for id in ids {
+        // Materials cache their bind groups, so fetching them mutably marks them as modified to pick up the new samplers.
+        materials.get_mut(id);
+    }
+}
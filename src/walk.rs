@@ -0,0 +1,118 @@
+//! Walk mode: keeps the fly camera on the ground at eye height, as a player on foot would see the world.
+
+use bevy::prelude::*;
+use bevy_flycam::{FlyCam, MovementSettings};
+
+use crate::terrain::TerrainQuery;
+
+/// Ground within this distance below the feet is stepped down onto instead of falling.
+const STEP_DOWN: f32 = 0.5;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WalkSettings {
+    pub enabled: bool,
+    /// Height of the camera above the ground, in yards.
+    pub eye_height: f32,
+    /// Horizontal speed in yards per second, in place of the fly camera's.
+    pub speed: f32,
+    /// Steepest slope in degrees that can be walked up.
+    pub max_slope: f32,
+    /// Upward speed when jumping, in yards per second.
+    pub jump_speed: f32,
+    /// In yards per second squared.
+    pub gravity: f32,
+}
+
+impl Default for WalkSettings {
+    fn default() -> Self {
+        // Roughly a human character running in the client.
+        Self {
+            enabled: false,
+            eye_height: 1.8,
+            speed: 7.0,
+            max_slope: 50.0,
+            jump_speed: 8.0,
+            gravity: 19.3,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct WalkState {
+    /// Where walking left the camera last frame, before the fly camera moved it.
+    position: Option<Vec3>,
+    vertical_speed: f32,
+    grounded: bool,
+}
+
+/// Turn the fly camera's movement this frame into walking: horizontal only, at walking speed,
+/// blocked by slopes that are too steep, with jumping and falling under gravity.
+/// Runs after the fly camera has moved, and before transforms are propagated so the view never shows its position.
+pub fn walk(
+    time: Res<Time>,
+    keys: Res<Input<KeyCode>>,
+    settings: Res<WalkSettings>,
+    movement: Res<MovementSettings>,
+    terrain: TerrainQuery,
+    mut camera: Query<&mut Transform, With<FlyCam>>,
+    mut state: Local<WalkState>,
+) {
+    if !settings.enabled {
+        state.position = None;
+        return
+    }
+
+    let mut transform = match camera.get_single_mut() {
+        Ok(transform) => transform,
+        Err(_) => return,
+    };
+    let previous = state.position.unwrap_or(transform.translation);
+    let delta = time.delta_seconds();
+
+    // The fly camera moves along where it's looking, so just dropping the vertical part would slow walking as the camera pitches.
+    // Keep the step's full length instead, pointed along the ground.
+    let step = transform.translation - previous;
+    let step = Vec3::new(step.x, 0.0, step.z).normalize_or_zero() * step.length();
+    let mut position = previous + step * settings.speed / movement.speed.max(f32::EPSILON);
+
+    // Refuse to climb slopes that are too steep, but allow walking down or along them.
+    if state.grounded {
+        if let Some(normal) = terrain.normal(position) {
+            let uphill = Vec3::new(-normal.x, 0.0, -normal.z);
+            if normal.y < settings.max_slope.to_radians().cos() && step.dot(uphill) > 0.0 {
+                position.x = previous.x;
+                position.z = previous.z;
+            }
+        }
+    }
+
+    if state.grounded && keys.just_pressed(KeyCode::Space) {
+        state.vertical_speed = settings.jump_speed;
+        state.grounded = false;
+    }
+
+    match terrain.height(position) {
+        Some(ground) => {
+            state.vertical_speed -= settings.gravity * delta;
+            position.y += state.vertical_speed * delta;
+
+            let feet = position.y - settings.eye_height;
+            let stepping_down = state.grounded && state.vertical_speed <= 0.0 && feet - ground < STEP_DOWN;
+            if feet <= ground || stepping_down {
+                position.y = ground + settings.eye_height;
+                state.vertical_speed = 0.0;
+                state.grounded = true;
+            } else {
+                state.grounded = false;
+            }
+        }
+        // Hold still vertically over tiles that haven't loaded yet, rather than falling through the world.
+        None => {
+            state.vertical_speed = 0.0;
+            state.grounded = false;
+        }
+    }
+
+    transform.translation = position;
+    state.position = Some(position);
+}
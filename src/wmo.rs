@@ -0,0 +1,375 @@
+//! WMO root and group files, and the system that places them on loaded tiles.
+
+use std::path::{Path, PathBuf};
+
+use bevy::{prelude::*, utils::hashbrown::HashMap};
+
+use crate::adt::AdtExtras;
+use crate::coordinates::{self, ADTPosition};
+use crate::doodads::{Doodad, ModelCache, ModelParts, ObjectSettings, SpawnedPlacements};
+use crate::m2::{self, M2Blend};
+use crate::raw;
+use crate::textures::TextureSettings;
+
+const MOMT_ENTRY_SIZE: usize = 64;
+const MODS_ENTRY_SIZE: usize = 32;
+const MODD_ENTRY_SIZE: usize = 40;
+const MOBA_ENTRY_SIZE: usize = 24;
+/// MOGP's header comes before its sub-chunks, inside the MOGP chunk itself.
+const MOGP_HEADER_SIZE: usize = 0x44;
+
+/// MOMT flag for materials that ignore lighting.
+const MATERIAL_UNLIT: u32 = 0x01;
+
+/// MODD stores flags in the top byte of its name offset.
+const DOODAD_NAME_MASK: u32 = 0x00FF_FFFF;
+
+/// Marker for entities drawing part of a WMO.
+#[derive(Component)]
+pub struct Wmo;
+
+#[derive(Debug, Clone)]
+pub struct WmoMaterial {
+    pub texture: Option<String>,
+    pub blend: M2Blend,
+    pub unlit: bool,
+}
+
+/// A doodad placed inside a WMO, relative to the WMO's origin.
+#[derive(Debug, Clone)]
+pub struct WmoDoodad {
+    pub filename: String,
+    pub position: Vec3,
+    /// Orientation in the WMO's own (Z up) axes.
+    pub rotation: Quat,
+    pub scale: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct WmoBatch {
+    pub indices: Vec<u32>,
+    pub material: usize,
+}
+
+/// Geometry of one group file, in the WMO's own coordinates (Z up).
+#[derive(Debug, Clone, Default)]
+pub struct WmoGroup {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<[f32; 2]>,
+    pub batches: Vec<WmoBatch>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct WmoRoot {
+    pub materials: Vec<WmoMaterial>,
+    pub groups: Vec<WmoGroup>,
+    /// Start and count of each MODS doodad set, indexing into `doodads`.
+    pub doodad_sets: Vec<(usize, usize)>,
+    pub doodads: Vec<WmoDoodad>,
+}
+
+impl WmoRoot {
+    /// Read a root WMO along with all of its group files.
+    pub fn from_file(path: &Path) -> Option<Self> {
+        let data = std::fs::read(path).ok()?;
+        let mut root = Self::default();
+
+        let mut group_count = 0;
+        let mut motx: &[u8] = &[];
+        let mut momt: &[u8] = &[];
+        let mut modn: &[u8] = &[];
+        for (magic, chunk) in raw::chunks(&data) {
+            match &magic {
+                b"MOHD" => group_count = raw::read_u32(chunk, 0x04)? as usize,
+                b"MOTX" => motx = chunk,
+                b"MOMT" => momt = chunk,
+                b"MODN" => modn = chunk,
+                b"MODS" => {
+                    root.doodad_sets = chunk.chunks_exact(MODS_ENTRY_SIZE)
+                        .filter_map(|set| Some((raw::read_u32(set, 20)? as usize, raw::read_u32(set, 24)? as usize)))
+                        .collect();
+                }
+                b"MODD" => {
+                    root.doodads = chunk.chunks_exact(MODD_ENTRY_SIZE)
+                        .filter_map(|doodad| {
+                            let name_offset = raw::read_u32(doodad, 0)? & DOODAD_NAME_MASK;
+                            Some(WmoDoodad {
+                                filename: raw::read_cstring(modn, name_offset as usize)?,
+                                position: raw::read_vec3(doodad, 4)?,
+                                rotation: Quat::from_xyzw(
+                                    raw::read_f32(doodad, 16)?,
+                                    raw::read_f32(doodad, 20)?,
+                                    raw::read_f32(doodad, 24)?,
+                                    raw::read_f32(doodad, 28)?,
+                                ),
+                                scale: raw::read_f32(doodad, 32)?,
+                            })
+                        })
+                        .collect();
+                }
+                _ => {}
+            }
+        }
+
+        root.materials = momt.chunks_exact(MOMT_ENTRY_SIZE)
+            .filter_map(|material| {
+                let flags = raw::read_u32(material, 0x00)?;
+                let texture = raw::read_cstring(motx, raw::read_u32(material, 0x0C)? as usize)
+                    .filter(|texture| !texture.is_empty());
+
+                Some(WmoMaterial {
+                    texture,
+                    blend: match raw::read_u32(material, 0x08)? {
+                        0 => M2Blend::Opaque,
+                        1 => M2Blend::AlphaKey,
+                        _ => M2Blend::Blend,
+                    },
+                    unlit: flags & MATERIAL_UNLIT != 0,
+                })
+            })
+            .collect();
+
+        // A missing or broken group only loses that part of the WMO.
+        root.groups = (0..group_count)
+            .filter_map(|index| WmoGroup::from_file(&group_path(path, index)))
+            .collect();
+
+        Some(root)
+    }
+}
+
+impl WmoGroup {
+    fn from_file(path: &Path) -> Option<Self> {
+        let data = std::fs::read(path).ok()?;
+        let (_, mogp) = raw::chunks(&data).find(|(magic, _)| magic == b"MOGP")?;
+
+        let mut group = Self::default();
+        let mut movi: Vec<u32> = Vec::new();
+        let mut moba: &[u8] = &[];
+        for (magic, chunk) in raw::chunks(mogp.get(MOGP_HEADER_SIZE..)?) {
+            match &magic {
+                b"MOVT" => group.positions = chunk.chunks_exact(12).filter_map(|v| raw::read_vec3(v, 0)).collect(),
+                b"MONR" => group.normals = chunk.chunks_exact(12).filter_map(|v| raw::read_vec3(v, 0)).collect(),
+                b"MOVI" => movi = chunk.chunks_exact(2).filter_map(|i| raw::read_u16(i, 0).map(|i| i as u32)).collect(),
+                // Groups with a second UV set have a second MOTV; only the first is used.
+                b"MOTV" if group.uvs.is_empty() => {
+                    group.uvs = chunk.chunks_exact(8)
+                        .filter_map(|uv| Some([raw::read_f32(uv, 0)?, raw::read_f32(uv, 4)?]))
+                        .collect();
+                }
+                b"MOBA" => moba = chunk,
+                _ => {}
+            }
+        }
+
+        let vertex_count = group.positions.len() as u32;
+        group.batches = moba.chunks_exact(MOBA_ENTRY_SIZE)
+            .filter_map(|batch| {
+                let start = raw::read_u32(batch, 0x0C)? as usize;
+                let count = raw::read_u16(batch, 0x10)? as usize;
+                let indices = movi.get(start..start + count)?;
+                if indices.iter().any(|i| *i >= vertex_count) {
+                    return None
+                }
+
+                Some(WmoBatch {
+                    indices: indices.to_vec(),
+                    material: raw::read_u8(batch, 0x17)? as usize,
+                })
+            })
+            .collect();
+
+        Some(group)
+    }
+}
+
+/// Path to one of a root WMO's groups, e.g. `Stormwind.wmo` -> `Stormwind_000.wmo`.
+fn group_path(path: &Path, index: usize) -> PathBuf {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+    path.with_file_name(format!("{}_{:03}.wmo", stem, index))
+}
+
+/// A loaded WMO's meshes, along with the doodads it can place.
+#[derive(Clone)]
+pub struct WmoParts {
+    pub groups: ModelParts,
+    pub doodad_sets: Vec<(usize, usize)>,
+    pub doodads: Vec<WmoDoodad>,
+}
+
+impl WmoParts {
+    /// Doodads shown for a placement: the default set, plus the set the placement picks.
+    pub fn doodads_in_set(&self, doodad_set: u16) -> impl Iterator<Item = &WmoDoodad> {
+        let mut sets = vec![0];
+        if doodad_set != 0 {
+            sets.push(doodad_set as usize);
+        }
+
+        sets.into_iter()
+            .filter_map(move |set| self.doodad_sets.get(set))
+            .flat_map(move |(start, count)| self.doodads.iter().skip(*start).take(*count))
+    }
+}
+
+/// Loaded WMOs, keyed by client path. Failed loads are kept as `None`.
+#[derive(Default)]
+pub struct WmoCache {
+    wmos: HashMap<String, Option<WmoParts>>,
+}
+
+impl WmoCache {
+    pub fn wmo(
+        &mut self,
+        filename: &str,
+        meshes: &mut Assets<Mesh>,
+        materials: &mut Assets<StandardMaterial>,
+        images: &mut ResMut<Assets<Image>>,
+        model_cache: &mut ModelCache,
+        texture_settings: &TextureSettings,
+    ) -> Option<WmoParts> {
+        if !self.wmos.contains_key(filename) {
+            let path = PathBuf::from(format!("./test_data/{}", filename.replace('\\', "/")));
+            let parts = WmoRoot::from_file(&path).map(|root| {
+                let group_materials: Vec<Handle<StandardMaterial>> = root.materials.iter()
+                    .map(|material| {
+                        let texture = material.texture.as_ref()
+                            .and_then(|texture| model_cache.texture(texture, images, texture_settings));
+
+                        materials.add(StandardMaterial {
+                            base_color: if texture.is_some() { Color::WHITE } else { Color::GRAY },
+                            base_color_texture: texture,
+                            alpha_mode: match material.blend {
+                                M2Blend::Opaque => AlphaMode::Opaque,
+                                M2Blend::AlphaKey => AlphaMode::Mask(0.5),
+                                M2Blend::Blend => AlphaMode::Blend,
+                            },
+                            unlit: material.unlit,
+                            perceptual_roughness: 0.9,
+                            reflectance: 0.1,
+                            // The axis swap flips the winding, so draw both sides like the M2s.
+                            double_sided: true,
+                            cull_mode: None,
+                            ..default()
+                        })
+                    })
+                    .collect();
+
+                let groups = root.groups.iter()
+                    .flat_map(|group| group.batches.iter().map(move |batch| (group, batch)))
+                    .filter_map(|(group, batch)| {
+                        let material = group_materials.get(batch.material)?.clone();
+                        let mesh = m2::batch_mesh(&group.positions, &group.normals, &group.uvs, &batch.indices);
+                        Some((meshes.add(mesh), material))
+                    })
+                    .collect();
+
+                WmoParts {
+                    groups,
+                    doodad_sets: root.doodad_sets,
+                    doodads: root.doodads,
+                }
+            });
+
+            self.wmos.insert(filename.to_string(), parts);
+        }
+
+        self.wmos.get(filename).cloned().flatten()
+    }
+}
+
+/// Transform of a WMO's doodad, relative to the WMO.
+fn doodad_transform(doodad: &WmoDoodad) -> Transform {
+    let rotation = doodad.rotation;
+
+    Transform {
+        translation: Vec3::new(doodad.position.x, doodad.position.z, doodad.position.y),
+        // Swapping Y and Z mirrors the rotation's axis and reverses its direction, as with placements.
+        rotation: Quat::from_xyzw(-rotation.x, -rotation.z, -rotation.y, rotation.w),
+        scale: Vec3::splat(doodad.scale),
+    }
+}
+
+/// Spawn the WMOs of each tile once its terrain has been rendered, along with their doodad sets.
+/// The entities are added to the entity list of the tile that spawned them, so they're despawned along with it.
+#[allow(clippy::too_many_arguments)]
+pub fn render_wmos(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut images: ResMut<Assets<Image>>,
+    mut wmo_cache: ResMut<WmoCache>,
+    mut model_cache: ResMut<ModelCache>,
+    extras_lookup: Res<HashMap<ADTPosition, AdtExtras>>,
+    mut adt_entities_lookup: ResMut<HashMap<ADTPosition, Vec<Entity>>>,
+    settings: Res<ObjectSettings>,
+    texture_settings: Res<TextureSettings>,
+    mut spawned: Local<SpawnedPlacements>,
+) {
+    spawned.forget_unloaded(&adt_entities_lookup);
+    if !settings.models {
+        return
+    }
+
+    for (position, entities) in adt_entities_lookup.iter_mut() {
+        if spawned.is_done(position) {
+            continue;
+        }
+        let extras = match extras_lookup.get(position) {
+            Some(extras) => extras,
+            None => continue,
+        };
+
+        for placement in &extras.wmos {
+            if spawned.is_spawned(placement.unique_id) {
+                continue;
+            }
+            spawned.spawn(placement.unique_id, position);
+            let transform = coordinates::placement_transform(placement.position, placement.rotation, placement.scale);
+
+            let wmo = match wmo_cache.wmo(&placement.filename, &mut meshes, &mut materials, &mut images, &mut model_cache, &texture_settings) {
+                Some(wmo) => wmo,
+                None => continue,
+            };
+
+            for (mesh, material) in wmo.groups.iter().cloned() {
+                let entity = commands
+                    .spawn_bundle(PbrBundle {
+                        mesh,
+                        material,
+                        transform,
+                        visibility: Visibility { is_visible: settings.shows(false, true, false) },
+                        ..default()
+                    })
+                    .insert(Wmo)
+                    .id();
+                entities.push(entity);
+            }
+
+            for doodad in wmo.doodads_in_set(placement.doodad_set) {
+                let parts = match model_cache.model(&doodad.filename, &mut meshes, &mut materials, &mut images, &texture_settings) {
+                    Some(parts) => parts,
+                    None => continue,
+                };
+
+                let doodad_transform = transform.mul_transform(doodad_transform(doodad));
+                for (mesh, material) in parts {
+                    let entity = commands
+                        .spawn_bundle(PbrBundle {
+                            mesh,
+                            material,
+                            transform: doodad_transform,
+                            visibility: Visibility { is_visible: settings.shows(true, true, false) },
+                            ..default()
+                        })
+                        .insert(Doodad)
+                        .insert(Wmo)
+                        .id();
+                    entities.push(entity);
+                }
+            }
+        }
+
+        spawned.done(position);
+    }
+}
@@ -0,0 +1,597 @@
+//! Borders between areas, traced along chunk edges from each MCNK's area ID.
+//! Drawn as outlines over the loaded terrain, and exported as GeoJSON for the whole map or just the loaded tiles.
+
+use std::path::{Path, PathBuf};
+
+use bevy::{prelude::*, tasks::{AsyncComputeTaskPool, Task}, utils::hashbrown::{HashMap, HashSet}};
+use futures_lite::future;
+
+use wow_chunky::files;
+
+use crate::adt::{self, AdtExtras};
+use crate::coordinates::{self, ADTPosition};
+use crate::dbc::{AreaRecord, DbcTable};
+use crate::lines;
+use crate::raw;
+
+/// Outlines float this far above the terrain, so they aren't hidden inside it.
+const OUTLINE_LIFT: f32 = 0.5;
+const MAP_TILES: u32 = 64;
+/// MAIN flag for a tile that has an ADT.
+const MAIN_HAS_ADT: u32 = 0x1;
+
+/// Which tiles to trace zone borders across when exporting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZoneScope {
+    Loaded,
+    AllTiles,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ZoneSettings {
+    /// Draw outlines where neighbouring loaded chunks are in different areas.
+    pub outlines: bool,
+    /// Export requested from the UI, handled and cleared by `export_zones`.
+    pub export: Option<ZoneScope>,
+}
+
+/// Outcome of the last export, and the tiles being read for an export of the whole map.
+#[derive(Default)]
+pub struct ZoneExport {
+    /// Shown in the Zones panel, so a failed write can be seen without a terminal.
+    pub status: Option<String>,
+    reading: Option<Task<HashMap<ChunkKey, u32>>>,
+}
+
+impl ZoneExport {
+    pub fn is_reading(&self) -> bool {
+        self.reading.is_some()
+    }
+}
+
+/// Marker for the mesh holding every zone outline.
+#[derive(Component)]
+pub struct ZoneOutline;
+
+/// Row and column of a chunk across the whole map, counted from the map's corner like `ADTPosition`.
+/// Row `r`, column `c` spans world X from `PLACEMENT_OFFSET - r * CHUNK_SIZE` down by one chunk, and the same for Y.
+type ChunkKey = (i32, i32);
+
+/// Key of the chunk whose corner (its largest X and Y, as stored in MCNK) is at a world position.
+fn chunk_key(x: f32, y: f32) -> ChunkKey {
+    (
+        ((coordinates::PLACEMENT_OFFSET - x) / coordinates::CHUNK_SIZE).round() as i32,
+        ((coordinates::PLACEMENT_OFFSET - y) / coordinates::CHUNK_SIZE).round() as i32,
+    )
+}
+
+/// World X and Y of a corner of the chunk grid.
+fn corner_position(row: i32, column: i32) -> (f32, f32) {
+    (
+        coordinates::PLACEMENT_OFFSET - row as f32 * coordinates::CHUNK_SIZE,
+        coordinates::PLACEMENT_OFFSET - column as f32 * coordinates::CHUNK_SIZE,
+    )
+}
+
+/// Area ID of every chunk in the loaded tiles.
+fn loaded_areas(
+    adts: &HashMap<ADTPosition, Option<files::ADT>>,
+    extras_lookup: &HashMap<ADTPosition, AdtExtras>,
+) -> HashMap<ChunkKey, u32> {
+    let mut areas = HashMap::new();
+    for (position, adt) in adts.iter() {
+        let (adt, extras) = match (adt, extras_lookup.get(position)) {
+            (Some(adt), Some(extras)) => (adt, extras),
+            _ => continue,
+        };
+
+        for (chunk, chunk_extras) in adt.mcnk.iter().zip(extras.chunks.iter()) {
+            areas.insert(chunk_key(chunk.position.x, chunk.position.y), chunk_extras.area_id);
+        }
+    }
+
+    areas
+}
+
+/// Tiles the WDT's MAIN chunk marks as having an ADT.
+fn present_tiles(wdt_path: &Path) -> Vec<ADTPosition> {
+    let data = match std::fs::read(wdt_path) {
+        Ok(data) => data,
+        Err(_) => return Vec::new(),
+    };
+    let main = match raw::chunks(&data).find(|(magic, _)| magic == b"MAIN") {
+        Some((_, main)) => main,
+        None => return Vec::new(),
+    };
+
+    // Entries are 8 bytes, flags then an unused async ID, in rows of Y like the WDL's MAOF.
+    (0..MAP_TILES)
+        .flat_map(|y| (0..MAP_TILES).map(move |x| ADTPosition { x, y }))
+        .filter(|position| {
+            raw::read_u32(main, ((position.y * MAP_TILES + position.x) * 8) as usize)
+                .map_or(false, |flags| flags & MAIN_HAS_ADT != 0)
+        })
+        .collect()
+}
+
+/// Area ID of every chunk in every tile of the map, read from the root ADTs alone.
+/// Reads up to 4096 files, so it's run on the task pool.
+fn all_areas(wdt_path: &Path) -> HashMap<ChunkKey, u32> {
+    let mut areas = HashMap::new();
+    for position in present_tiles(wdt_path) {
+        let path = adt::tile_path(wdt_path, &position);
+        for (position, area_id) in adt::read_chunk_areas(&path) {
+            areas.insert(chunk_key(position.x, position.y), area_id);
+        }
+    }
+
+    areas
+}
+
+/// Line segments along every edge between loaded chunks in different areas.
+/// Each edge is drawn from the chunk above or to the left of it, following that chunk's outer vertices.
+fn outline_segments(
+    adts: &HashMap<ADTPosition, Option<files::ADT>>,
+    areas: &HashMap<ChunkKey, u32>,
+) -> Vec<(Vec3, Vec3)> {
+    let mut segments = Vec::new();
+    for adt in adts.values().flatten() {
+        for chunk in adt.mcnk.iter() {
+            let (row, column) = chunk_key(chunk.position.x, chunk.position.y);
+            let area_id = match areas.get(&(row, column)) {
+                Some(area_id) => *area_id,
+                None => continue,
+            };
+
+            // Outer vertices are the first 9 of every 17 heights in MCVT.
+            let outer: Vec<Vec3> = (0..9)
+                .flat_map(|i| (0..9).map(move |j| i * 17 + j))
+                .filter_map(|index| chunk.mcvt.heights.get(index))
+                .map(|position| Vec3::new(position.x, position.y, position.z))
+                .collect();
+
+            // The bottom side is a line of constant X, the right side one of constant Y.
+            let (bottom_x, right_y) = corner_position(row + 1, column + 1);
+            let sides = [
+                ((row + 1, column), bottom_x, true),
+                ((row, column + 1), right_y, false),
+            ];
+
+            for (neighbour, line, constant_x) in sides {
+                match areas.get(&neighbour) {
+                    Some(neighbour_area) if *neighbour_area != area_id => {}
+                    _ => continue,
+                }
+
+                // The side's vertices are the ones on its line, ordered along it.
+                let across = |vertex: &Vec3| if constant_x { vertex.x } else { vertex.y };
+                let along = |vertex: &Vec3| if constant_x { vertex.y } else { vertex.x };
+                let mut edge: Vec<Vec3> = outer.iter()
+                    .filter(|vertex| (across(vertex) - line).abs() < coordinates::CHUNK_SIZE / 16.0)
+                    .copied()
+                    .collect();
+                edge.sort_by(|a, b| along(a).total_cmp(&along(b)));
+
+                // Swap into Bevy's axes, like the terrain mesh.
+                segments.extend(edge.windows(2).map(|pair| (
+                    Vec3::new(pair[0].x, pair[0].z + OUTLINE_LIFT, pair[0].y),
+                    Vec3::new(pair[1].x, pair[1].z + OUTLINE_LIFT, pair[1].y),
+                )));
+            }
+        }
+    }
+
+    segments
+}
+
+/// Rebuild the outlines whenever the set of rendered tiles changes, or they're toggled.
+#[allow(clippy::too_many_arguments)]
+pub fn render_zone_outlines(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    settings: Res<ZoneSettings>,
+    adts: Res<HashMap<ADTPosition, Option<files::ADT>>>,
+    extras_lookup: Res<HashMap<ADTPosition, AdtExtras>>,
+    adt_entities_lookup: Res<HashMap<ADTPosition, Vec<Entity>>>,
+    outlines: Query<Entity, With<ZoneOutline>>,
+    mut outlined: Local<Option<HashSet<ADTPosition>>>,
+) {
+    let rendered: HashSet<ADTPosition> = adt_entities_lookup.keys().cloned().collect();
+    let wanted = if settings.outlines { Some(rendered) } else { None };
+    if *outlined == wanted {
+        return
+    }
+
+    for entity in &outlines {
+        commands.entity(entity).despawn();
+    }
+    *outlined = wanted;
+    if outlined.is_none() {
+        return
+    }
+
+    let segments = outline_segments(&adts, &loaded_areas(&adts, &extras_lookup));
+    if segments.is_empty() {
+        return
+    }
+
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(lines::line_mesh(&segments)),
+            material: materials.add(lines::line_material(Color::rgb(1.0, 0.85, 0.2))),
+            ..default()
+        })
+        .insert(ZoneOutline);
+}
+
+/// Closed rings around each area's chunks, as corners of the chunk grid.
+/// Every chunk contributes the sides it doesn't share with a chunk of the same area, all wound the same way,
+/// so an area's sides join up into its outer borders and the borders of any holes.
+/// Rings can touch each other at a corner, but never cross or touch themselves.
+fn trace_rings(areas: &HashMap<ChunkKey, u32>) -> HashMap<u32, Vec<Vec<ChunkKey>>> {
+    let mut edges: HashMap<u32, HashMap<ChunkKey, Vec<ChunkKey>>> = HashMap::new();
+    for (&(row, column), &area_id) in areas.iter() {
+        let sides = [
+            ((row, column), (row, column + 1), (row - 1, column)),
+            ((row, column + 1), (row + 1, column + 1), (row, column + 1)),
+            ((row + 1, column + 1), (row + 1, column), (row + 1, column)),
+            ((row + 1, column), (row, column), (row, column - 1)),
+        ];
+
+        for (start, end, neighbour) in sides {
+            if areas.get(&neighbour) != Some(&area_id) {
+                edges.entry(area_id).or_default().entry(start).or_default().push(end);
+            }
+        }
+    }
+
+    edges.into_iter()
+        .map(|(area_id, mut edges)| {
+            let mut rings = Vec::new();
+            while let Some(&start) = edges.keys().next() {
+                let first = take_edge(&mut edges, start, None);
+                let mut ring = vec![start, first];
+                let (mut previous, mut current) = (start, first);
+
+                // Where chunks of the area only touch at a corner, that corner has two sides leaving it.
+                // Always taking the sharpest turn keeps to the chunk being traced around, so rings never cross themselves or each other.
+                // Back at the start, the ring is closed once the sharpest turn would lead onto its first side again.
+                loop {
+                    let mut ends = edges.get(&current).cloned().unwrap_or_default();
+                    if current == start {
+                        ends.push(first);
+                    }
+                    let next = match ends.into_iter().min_by_key(|&end| turn(previous, current, end)) {
+                        Some(next) => next,
+                        None => break,
+                    };
+                    if current == start && next == first {
+                        break;
+                    }
+
+                    take_edge(&mut edges, current, Some(next));
+                    ring.push(next);
+                    previous = current;
+                    current = next;
+                }
+
+                rings.extend(split_ring(ring).into_iter().map(simplify_ring));
+            }
+
+            (area_id, rings)
+        })
+        .collect()
+}
+
+/// Split a ring wherever it passes through the same corner twice, which happens where a hole touches its outer border at a corner.
+/// Each loop cut out is a ring of its own, wound the way its side of the border is.
+fn split_ring(ring: Vec<ChunkKey>) -> Vec<Vec<ChunkKey>> {
+    let mut rings = Vec::new();
+    let mut path: Vec<ChunkKey> = Vec::new();
+    let mut visited: HashMap<ChunkKey, usize> = HashMap::new();
+    for corner in ring {
+        if let Some(&index) = visited.get(&corner) {
+            let mut loop_ring: Vec<ChunkKey> = path.drain(index..).collect();
+            for other in &loop_ring {
+                visited.remove(other);
+            }
+            loop_ring.push(corner);
+            rings.push(loop_ring);
+        }
+        visited.insert(corner, path.len());
+        path.push(corner);
+    }
+
+    rings
+}
+
+/// Remove a side leaving `start` from the sides left to trace, either the given one or any, and return where it ends.
+fn take_edge(edges: &mut HashMap<ChunkKey, Vec<ChunkKey>>, start: ChunkKey, end: Option<ChunkKey>) -> ChunkKey {
+    let ends = edges.get_mut(&start).expect("corners with no sides left are removed");
+    let index = end.and_then(|end| ends.iter().position(|&other| other == end)).unwrap_or(ends.len() - 1);
+    let end = ends.swap_remove(index);
+    if ends.is_empty() {
+        edges.remove(&start);
+    }
+
+    end
+}
+
+/// Which way the border turns at `current`: -1 for the same turn a chunk's own sides make, 0 for straight on, and 1 for the other way.
+fn turn(previous: ChunkKey, current: ChunkKey, next: ChunkKey) -> i32 {
+    let incoming = (current.0 - previous.0, current.1 - previous.1);
+    let outgoing = (next.0 - current.0, next.1 - current.1);
+    incoming.0 * outgoing.1 - incoming.1 * outgoing.0
+}
+
+/// Drop the corners along straight runs, keeping the ring closed.
+fn simplify_ring(ring: Vec<ChunkKey>) -> Vec<ChunkKey> {
+    let mut simplified: Vec<ChunkKey> = Vec::new();
+    for corner in ring {
+        if simplified.len() >= 2 {
+            let (a, b) = (simplified[simplified.len() - 2], simplified[simplified.len() - 1]);
+            if (b.0 - a.0) * (corner.1 - b.1) == (b.1 - a.1) * (corner.0 - b.0) {
+                simplified.pop();
+            }
+        }
+        simplified.push(corner);
+    }
+
+    simplified
+}
+
+/// Twice the signed area of a ring, positive for outer borders as `trace_rings` winds them.
+fn signed_area(ring: &[ChunkKey]) -> i64 {
+    ring.windows(2)
+        .map(|pair| pair[0].1 as i64 * pair[1].0 as i64 - pair[1].1 as i64 * pair[0].0 as i64)
+        .sum()
+}
+
+fn contains(ring: &[ChunkKey], point: (f32, f32)) -> bool {
+    let mut inside = false;
+    for pair in ring.windows(2) {
+        let (a, b) = ((pair[0].0 as f32, pair[0].1 as f32), (pair[1].0 as f32, pair[1].1 as f32));
+        if (a.0 > point.0) != (b.0 > point.0) && point.1 < a.1 + (point.0 - a.0) / (b.0 - a.0) * (b.1 - a.1) {
+            inside = !inside;
+        }
+    }
+
+    inside
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::from("\"");
+    for character in value.chars() {
+        match character {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            character if character.is_control() => escaped.push_str(&format!("\\u{:04x}", character as u32)),
+            character => escaped.push(character),
+        }
+    }
+    escaped.push('"');
+
+    escaped
+}
+
+/// World X and Y of a ring's corners, in the order GeoJSON wants them.
+/// World X grows northwards and Y westwards, the reverse of rows and columns, which turns `trace_rings`' outer borders clockwise.
+/// Walking the ring backwards makes them counter-clockwise again, with holes clockwise, as RFC 7946 asks.
+fn world_ring(ring: &[ChunkKey]) -> Vec<(f32, f32)> {
+    ring.iter().rev().map(|&(row, column)| corner_position(row, column)).collect()
+}
+
+fn json_ring(ring: &[ChunkKey]) -> String {
+    let corners: Vec<String> = world_ring(ring).iter()
+        .map(|(x, y)| format!("[{:.3},{:.3}]", x, y))
+        .collect();
+
+    format!("[{}]", corners.join(","))
+}
+
+/// Sort an area's rings into polygons: each outer border, followed by the borders of the holes in it.
+fn group_polygons(rings: Vec<Vec<ChunkKey>>) -> Vec<Vec<Vec<ChunkKey>>> {
+    let (outers, holes): (Vec<_>, Vec<_>) = rings.into_iter().partition(|ring| signed_area(ring) > 0);
+
+    // Each hole belongs to the smallest outer border around it.
+    // Rings never share a side, so the middle of a hole's first side is never on an outer border.
+    let mut polygons: Vec<Vec<Vec<ChunkKey>>> = outers.into_iter().map(|outer| vec![outer]).collect();
+    for hole in holes {
+        let middle = (
+            (hole[0].0 + hole[1].0) as f32 / 2.0,
+            (hole[0].1 + hole[1].1) as f32 / 2.0,
+        );
+        let polygon = polygons.iter_mut()
+            .filter(|polygon| contains(&polygon[0], middle))
+            .min_by_key(|polygon| signed_area(&polygon[0]));
+        if let Some(polygon) = polygon {
+            polygon.push(hole);
+        }
+    }
+
+    polygons
+}
+
+/// A FeatureCollection with a MultiPolygon per area.
+/// Positions are `[x, y]` in world coordinates, where X points north and Y west, so a viewer treating X as east shows the map turned a quarter turn.
+/// Outer rings are counter-clockwise and holes clockwise in that plane, following the RFC 7946 right-hand rule.
+fn zones_geojson(areas: &HashMap<ChunkKey, u32>, area_names: &DbcTable<AreaRecord>) -> String {
+    let mut rings: Vec<(u32, Vec<Vec<ChunkKey>>)> = trace_rings(areas).into_iter().collect();
+    rings.sort_by_key(|(area_id, _)| *area_id);
+
+    let features: Vec<String> = rings.into_iter()
+        .map(|(area_id, rings)| {
+            let polygons = group_polygons(rings);
+            let polygons: Vec<String> = polygons.iter()
+                .map(|polygon| format!("[{}]", polygon.iter().map(|ring| json_ring(ring)).collect::<Vec<_>>().join(",")))
+                .collect();
+
+            format!(
+                "{{\"type\":\"Feature\",\"properties\":{{\"area_id\":{},\"name\":{}}},\"geometry\":{{\"type\":\"MultiPolygon\",\"coordinates\":[{}]}}}}",
+                area_id,
+                json_string(&area_names.full_name(area_id)),
+                polygons.join(","),
+            )
+        })
+        .collect();
+
+    format!("{{\"type\":\"FeatureCollection\",\"features\":[\n{}\n]}}\n", features.join(",\n"))
+}
+
+/// Write zone borders next to the working directory, as `{map}_zones.geojson`.
+fn write_zones(wdt: &files::WDT, areas: &HashMap<ChunkKey, u32>, area_names: &DbcTable<AreaRecord>) -> String {
+    let map_name = wdt.path.file_stem().and_then(|name| name.to_str()).unwrap_or("map");
+    let path = PathBuf::from(format!("./{}_zones.geojson", map_name));
+    match std::fs::write(&path, zones_geojson(areas, area_names)) {
+        Ok(_) => {
+            let status = format!("Exported {} chunks of zone borders to {}", areas.len(), path.display());
+            info!("{}", status);
+            status
+        }
+        Err(error) => {
+            let status = format!("Couldn't write {}: {}", path.display(), error);
+            warn!("{}", status);
+            status
+        }
+    }
+}
+
+/// Export the zone borders requested from the UI.
+/// Loaded tiles are written straight away, while the whole map is read on the task pool and written once it's done.
+pub fn export_zones(
+    mut settings: ResMut<ZoneSettings>,
+    mut export: ResMut<ZoneExport>,
+    wdt: Res<files::WDT>,
+    adts: Res<HashMap<ADTPosition, Option<files::ADT>>>,
+    extras_lookup: Res<HashMap<ADTPosition, AdtExtras>>,
+    area_names: Res<DbcTable<AreaRecord>>,
+) {
+    let finished = export.reading.as_mut().and_then(|task| future::block_on(future::poll_once(task)));
+    if let Some(areas) = finished {
+        export.reading = None;
+        export.status = Some(write_zones(&wdt, &areas, &area_names));
+    }
+
+    let scope = match settings.export {
+        Some(scope) => scope,
+        None => return,
+    };
+    settings.export = None;
+
+    match scope {
+        ZoneScope::Loaded => {
+            let areas = loaded_areas(&adts, &extras_lookup);
+            export.status = Some(write_zones(&wdt, &areas, &area_names));
+        }
+        // The UI doesn't offer another export of the whole map until this one is done.
+        ZoneScope::AllTiles if !export.is_reading() => {
+            let wdt_path = wdt.path.clone();
+            export.reading = Some(AsyncComputeTaskPool::get().spawn(async move { all_areas(&wdt_path) }));
+            export.status = Some("Reading every tile...".to_string());
+        }
+        ZoneScope::AllTiles => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn areas(chunks: &[(ChunkKey, u32)]) -> HashMap<ChunkKey, u32> {
+        chunks.iter().copied().collect()
+    }
+
+    /// A ring is closed, and visits no corner twice on the way round.
+    fn assert_simple(ring: &[ChunkKey]) {
+        assert_eq!(ring.first(), ring.last());
+        let corners: HashSet<ChunkKey> = ring[1..].iter().copied().collect();
+        assert_eq!(corners.len(), ring.len() - 1, "ring touches itself: {:?}", ring);
+    }
+
+    #[test]
+    fn checkerboard_corners_stay_separate() {
+        let areas = areas(&[((0, 0), 1), ((1, 1), 1), ((0, 1), 2), ((1, 0), 2)]);
+        let rings = trace_rings(&areas);
+
+        for area_id in [1, 2] {
+            let rings = &rings[&area_id];
+            assert_eq!(rings.len(), 2);
+            for ring in rings {
+                assert_simple(ring);
+                assert_eq!(ring.len(), 5);
+                assert_eq!(signed_area(ring), 2);
+            }
+        }
+    }
+
+    #[test]
+    fn ring_with_hole() {
+        let mut chunks: Vec<(ChunkKey, u32)> = (0..3).flat_map(|row| (0..3).map(move |column| ((row, column), 1))).collect();
+        chunks.retain(|(key, _)| *key != (1, 1));
+        chunks.push(((1, 1), 2));
+        let rings = trace_rings(&areas(&chunks));
+
+        let outer = &rings[&1];
+        assert_eq!(outer.len(), 2);
+        outer.iter().for_each(|ring| assert_simple(ring));
+
+        let polygons = group_polygons(outer.clone());
+        assert_eq!(polygons.len(), 1);
+        assert_eq!(polygons[0].len(), 2);
+        assert_eq!(signed_area(&polygons[0][0]), 18);
+        assert_eq!(signed_area(&polygons[0][1]), -2);
+
+        let inner = &rings[&2];
+        assert_eq!(inner.len(), 1);
+        assert_eq!(signed_area(&inner[0]), 2);
+    }
+
+    #[test]
+    fn hole_touching_its_border_at_a_corner() {
+        // Area 1 is a 3x3 block with a hole at the centre, and another chunk of area 2 at a corner, touching the hole diagonally.
+        let chunks: Vec<(ChunkKey, u32)> = (0..3)
+            .flat_map(|row| (0..3).map(move |column| ((row, column), if (row, column) == (1, 1) || (row, column) == (2, 2) { 2 } else { 1 })))
+            .collect();
+        let rings = trace_rings(&areas(&chunks));
+
+        for rings in rings.values() {
+            rings.iter().for_each(|ring| assert_simple(ring));
+        }
+        assert_eq!(rings[&1].len(), 2);
+        assert_eq!(rings[&2].len(), 2);
+        let total: i64 = rings[&1].iter().map(|ring| signed_area(ring)).sum();
+        assert_eq!(total, 14);
+
+        let polygons = group_polygons(rings[&1].clone());
+        assert_eq!(polygons.len(), 1);
+        assert_eq!(polygons[0].len(), 2);
+    }
+
+    #[test]
+    fn exported_rings_follow_the_right_hand_rule() {
+        // Twice the area of a ring as written to the file, positive when counter-clockwise in world X and Y.
+        fn world_area(ring: &[ChunkKey]) -> f64 {
+            let corners = world_ring(ring);
+            let (origin_x, origin_y) = (corners[0].0 as f64, corners[0].1 as f64);
+            corners.windows(2)
+                .map(|pair| {
+                    let (x0, y0) = (pair[0].0 as f64 - origin_x, pair[0].1 as f64 - origin_y);
+                    let (x1, y1) = (pair[1].0 as f64 - origin_x, pair[1].1 as f64 - origin_y);
+                    x0 * y1 - x1 * y0
+                })
+                .sum()
+        }
+
+        let mut chunks: Vec<(ChunkKey, u32)> = (0..3).flat_map(|row| (0..3).map(move |column| ((row, column), 1))).collect();
+        chunks.retain(|(key, _)| *key != (1, 1));
+        chunks.push(((1, 1), 2));
+        let rings = trace_rings(&areas(&chunks));
+
+        let polygons = group_polygons(rings[&1].clone());
+        let chunk_area = (coordinates::CHUNK_SIZE as f64).powi(2);
+        assert!((world_area(&polygons[0][0]) - 18.0 * chunk_area).abs() < chunk_area / 100.0);
+        assert!((world_area(&polygons[0][1]) + 2.0 * chunk_area).abs() < chunk_area / 100.0);
+        assert!(world_area(&rings[&2][0]) > 0.0);
+    }
+}